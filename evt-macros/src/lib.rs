@@ -0,0 +1,118 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Error, FnArg, ImplItem, ImplItemFn, ItemImpl, Path};
+
+const HANDLER_ATTRIBUTE: &str = "handler";
+
+struct HandlerMethod {
+    message_type: Path,
+    method: ImplItemFn,
+}
+
+// Implements `evt::consumer::Handler` for an impl block whose methods are
+// marked with `#[handler(MessageType)]`. Handler methods take a
+// `Message<MessageType>` and optionally a `&mut Context`, and return
+// `Result<(), evt::Error>`. Message types without a handler are ignored.
+#[proc_macro_attribute]
+pub fn handlers(_attributes: TokenStream, item: TokenStream) -> TokenStream {
+    let mut item = parse_macro_input!(item as ItemImpl);
+
+    match handler_methods(&mut item) {
+        Ok(methods) => {
+            let handler = implement_handler(&item, &methods);
+
+            quote!(#item #handler).into()
+        }
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+// Collects the marked methods, removing the `handler` attributes so that
+// they don't need to resolve to a macro of their own
+fn handler_methods(item: &mut ItemImpl) -> Result<Vec<HandlerMethod>, Error> {
+    let mut methods = vec![];
+
+    for impl_item in item.items.iter_mut() {
+        let method = match impl_item {
+            ImplItem::Fn(method) => method,
+            _ => continue,
+        };
+
+        let position = method
+            .attrs
+            .iter()
+            .position(|attribute| attribute.path().is_ident(HANDLER_ATTRIBUTE));
+
+        if let Some(position) = position {
+            let attribute = method.attrs.remove(position);
+            let message_type: Path = attribute.parse_args()?;
+
+            methods.push(HandlerMethod {
+                message_type,
+                method: method.clone(),
+            });
+        }
+    }
+
+    Ok(methods)
+}
+
+fn implement_handler(item: &ItemImpl, methods: &[HandlerMethod]) -> TokenStream2 {
+    let self_type = &item.self_ty;
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+
+    let message_types = methods.iter().map(|handler| {
+        let message_type = &handler.message_type;
+
+        quote!(<#message_type as ::evt::messaging::MessageType>::message_type())
+    });
+
+    let dispatches = methods.iter().map(|handler| {
+        let message_type = &handler.message_type;
+        let name = &handler.method.sig.ident;
+        let typed_inputs = handler
+            .method
+            .sig
+            .inputs
+            .iter()
+            .filter(|input| matches!(input, FnArg::Typed(_)))
+            .count();
+
+        let call = if typed_inputs > 1 {
+            quote!(self.#name(message, context))
+        } else {
+            quote!(self.#name(message))
+        };
+
+        quote! {
+            if message_data.message_type
+                == <#message_type as ::evt::messaging::MessageType>::message_type()
+            {
+                let message: ::evt::messaging::Message<#message_type> =
+                    ::std::convert::TryFrom::try_from(message_data)?;
+
+                return #call;
+            }
+        }
+    });
+
+    quote! {
+        impl #impl_generics ::evt::consumer::Handler for #self_type #where_clause {
+            fn message_types(&self) -> ::std::vec::Vec<::std::string::String> {
+                ::std::vec![#(#message_types),*]
+            }
+
+            #[allow(unused_variables)]
+            fn handle(
+                &mut self,
+                message_data: ::evt::message_store::MessageData,
+                context: &mut ::evt::consumer::Context<'_>,
+            ) -> ::std::result::Result<(), ::evt::Error> {
+                #(#dispatches)*
+
+                Ok(())
+            }
+        }
+    }
+}
@@ -1,17 +1,30 @@
+use std::convert::TryFrom;
+
+use evt::component_host;
+use evt::consumer::{Consumer, Context, Settings, SimpleBackOff};
+use evt::message_store::{MessageData, Read};
+use evt::messaging::{Message, MessageType, Write};
+use evt::{handlers, stream_name, Error, MessageStore};
 use serde::{Deserialize, Serialize};
 
 // Deposit command message
 // Send to the account service to effect a deposit
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 struct Deposit {
     account_id: String,
     amount: usize,
     time: String,
 }
 
+impl MessageType for Deposit {
+    fn message_type() -> String {
+        String::from("Deposit")
+    }
+}
+
 // Deposited event message
 // Event is written by the handler when a deposit is successfully processed
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 struct Deposited {
     account_id: String,
     amount: usize,
@@ -19,18 +32,30 @@ struct Deposited {
     processed_time: String,
 }
 
+impl MessageType for Deposited {
+    fn message_type() -> String {
+        String::from("Deposited")
+    }
+}
+
 // Withdraw command message
 // Send to the account service to effect a withdrawal
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 struct Withdraw {
     account_id: String,
     amount: usize,
     time: String,
 }
 
+impl MessageType for Withdraw {
+    fn message_type() -> String {
+        String::from("Withdraw")
+    }
+}
+
 // Withdrawn event message
 // Event is written by the handler when a withdrawal is successfully processed
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 struct Withdrawn {
     account_id: String,
     amount: usize,
@@ -38,10 +63,16 @@ struct Withdrawn {
     processed_time: String,
 }
 
+impl MessageType for Withdrawn {
+    fn message_type() -> String {
+        String::from("Withdrawn")
+    }
+}
+
 // WithdrawalRejected event message
 // Event is written by the handler when a withdrawal cannot be successfully
 // processed, as when there are insufficient funds
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 struct WithdrawalRejected {
     account_id: String,
     amount: usize,
@@ -49,6 +80,12 @@ struct WithdrawalRejected {
     processed_time: String,
 }
 
+impl MessageType for WithdrawalRejected {
+    fn message_type() -> String {
+        String::from("WithdrawalRejected")
+    }
+}
+
 // Account entity
 // The account service's model object
 struct Account {
@@ -88,83 +125,119 @@ impl Account {
 //     }
 // }
 //
-// // Account command handler with withdrawal implementation
-// // Business logic for processing a withdrawal
-// #[derive(evt::Handler)]
-// struct Handler {
-//     store: &MessageStore,
-//     category: String,
-// }
-//
-// impl Clock for Handler {}
-//
-// impl EntityStore<Account> for Handler {
-//     fn get_store(&self) -> &MessageStore {
-//         this.store
-//     }
-// }
-//
-//
-// impl WriteMessage for Handler {
-//     fn get_store(&self) -> &MessageStore {
-//         this.store
-//     }
-// }
-//
-// impl Handler {
-//     #[handler(Deposit)]
-//     fn handle_deposit(&self, depsit: Message<Deposit>) {
-//         let account_id = deposit.account_id;
-//
-//         let time = self.iso8601();
-//
-//         let mut deposited = Message<Deposited>.follow(&deposit);
-//         deposited.processed_time = time;
-//
-//         let stream_name = stream_name!(&self.category, id = &account_id);
-//
-//         self.write(deposited, stream_name);
-//     }
-//
-//     #[handler(Withdraw)]
-//     fn handle_withdraw(&self, withdraw: Message<Withdraw>) {
-//         let account_id = withdraw.account_id;
-//
-//         let account = self.fetch<Account>(&account_id);
-//
-//         let time = self.iso8601();
-//
-//         let stream_name = stream_name!(&self.category, id = &account_id);
-//
-//         if !account.has_sufficient_funds(withdraw.amount) {
-//             let mut withdrawal_rejected = Message<WithdrawalRejected>.follow(&withdraw);
-//             withdrawal_rejected.time = time;
-//
-//             self.write(withdrawal_rejected, stream_name);
-//             return;
-//         }
-//
-//         let mut withdrawn = Message<Withdrawn>.follow(&withdraw);
-//         withdrawn.time = time;
-//
-//         self.write(withdrawn, stream_name);
-//     }
-// }
-//
-//
-// fn start(settings: Settings) {
-//     let store = MessageStore.build(settings);
-//
-//     let handler = Handler { category: "account", store };
-//
-//     let consumer = Consumer { handler };
-//
-//     // this has some ???
-//     consumer.start(stream_name!("account", type = "command"));
-// }
-//
+// Account command handler
+// Business logic for processing deposits and withdrawals
+struct Handler {
+    category: String,
+}
+
+#[handlers]
+impl Handler {
+    #[handler(Deposit)]
+    fn handle_deposit(
+        &mut self,
+        deposit: Message<Deposit>,
+        context: &mut Context,
+    ) -> Result<(), Error> {
+        let deposit = deposit.into_inner();
+
+        let deposited = Message::from_t(Deposited {
+            account_id: deposit.account_id.clone(),
+            amount: deposit.amount,
+            time: deposit.time,
+            processed_time: context.clock.rfc3339(),
+        });
+
+        let stream_name = stream_name!(&self.category, id = &deposit.account_id);
+
+        context.store.write(&deposited, &stream_name, None)?;
+
+        Ok(())
+    }
+
+    #[handler(Withdraw)]
+    fn handle_withdraw(
+        &mut self,
+        withdraw: Message<Withdraw>,
+        context: &mut Context,
+    ) -> Result<(), Error> {
+        let withdraw = withdraw.into_inner();
+
+        let stream_name = stream_name!(&self.category, id = &withdraw.account_id);
+
+        let account = fetch(context.store, &stream_name)?;
+
+        let time = context.clock.rfc3339();
+
+        if !account.has_sufficient_funds(withdraw.amount) {
+            let withdrawal_rejected = Message::from_t(WithdrawalRejected {
+                account_id: withdraw.account_id,
+                amount: withdraw.amount,
+                time: withdraw.time,
+                processed_time: time,
+            });
+
+            context
+                .store
+                .write(&withdrawal_rejected, &stream_name, None)?;
+
+            return Ok(());
+        }
+
+        let withdrawn = Message::from_t(Withdrawn {
+            account_id: withdraw.account_id,
+            amount: withdraw.amount,
+            time: withdraw.time,
+            processed_time: time,
+        });
+
+        context.store.write(&withdrawn, &stream_name, None)?;
+
+        Ok(())
+    }
+}
+
+// Stands in for the entity store until the projection above exists
+fn fetch(store: &mut MessageStore, stream_name: &str) -> Result<Account, Error> {
+    let mut account = Account {
+        id: String::new(),
+        balance: 0,
+    };
+
+    for message_data in store.read(stream_name) {
+        let message_data: MessageData = message_data?;
+
+        if let Ok(deposited) = Message::<Deposited>::try_from(message_data.clone()) {
+            let deposited = deposited.into_inner();
+            account.deposit(deposited.amount);
+            account.id = deposited.account_id;
+        } else if let Ok(withdrawn) = Message::<Withdrawn>::try_from(message_data) {
+            let withdrawn = withdrawn.into_inner();
+            account.withdraw(withdrawn.amount);
+            account.id = withdrawn.account_id;
+        }
+    }
+
+    Ok(account)
+}
+
+// Account command consumer
+// Built again by the component host whenever it has to be restarted
+fn start() -> Result<Consumer<SimpleBackOff>, Error> {
+    let store = MessageStore::build();
+    let category = stream_name!("account", category_type = "command");
+
+    let mut consumer = Consumer::new(category, store, Settings::default());
+
+    consumer.register(Handler {
+        category: String::from("account"),
+    });
+
+    Ok(consumer)
+}
+
 fn main() {
-    // component_host.start("account-service", |h| {
-    //   h.register(start)
-    // })
+    component_host::start("account-service", |h| {
+        h.register("account-command", start);
+    })
 }
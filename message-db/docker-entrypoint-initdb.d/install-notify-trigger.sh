@@ -0,0 +1,21 @@
+#!/bin/sh
+
+# Publishes the category of every new message on the "messages" channel so
+# consumers can LISTEN for new messages instead of polling on an interval
+
+psql -v ON_ERROR_STOP=1 -d message_store <<SQL
+CREATE OR REPLACE FUNCTION message_store.notify_message()
+RETURNS trigger
+AS \$\$
+BEGIN
+  PERFORM pg_notify('messages', message_store.category(NEW.stream_name));
+  RETURN NEW;
+END;
+\$\$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS notify_message ON message_store.messages;
+
+CREATE TRIGGER notify_message
+  AFTER INSERT ON message_store.messages
+  FOR EACH ROW EXECUTE PROCEDURE message_store.notify_message();
+SQL
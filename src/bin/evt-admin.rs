@@ -0,0 +1,398 @@
+// Operator commands for a message store, connecting with MESSAGE_STORE_URL
+//
+//     evt-admin lag <category> [--identifier <identifier>]
+//     evt-admin reset <category> (--start | --position <global position> | --time <rfc3339>)
+//         [--identifier <identifier>] [--dry-run]
+//     evt-admin message <id>
+use std::env;
+use std::process;
+
+use evt::consumer::{consumer_status, reset_position, ConsumerStatus, Reset, ResetTo};
+use evt::message_store::{Get, MessageData};
+use evt::{DateTime, Error, MessageStore, Utc, Uuid};
+
+const USAGE: &str = "\
+Usage:
+    evt-admin lag <category> [--identifier <identifier>]
+    evt-admin reset <category> (--start | --position <global position> | --time <rfc3339>)
+        [--identifier <identifier>] [--dry-run]
+    evt-admin message <id>";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Lag {
+        category: String,
+        identifier: Option<String>,
+    },
+    Reset {
+        category: String,
+        identifier: Option<String>,
+        to: ResetTo,
+        dry_run: bool,
+    },
+    Message {
+        id: Uuid,
+    },
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    identifier: Option<String>,
+    start: bool,
+    position: Option<i64>,
+    time: Option<DateTime<Utc>>,
+    dry_run: bool,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let command = match parse(&args) {
+        Some(command) => command,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(command) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse(args: &[String]) -> Option<Command> {
+    let (command, args) = args.split_first()?;
+
+    match command.as_str() {
+        "lag" => {
+            let (category, options) = args.split_first()?;
+            let options = parse_options(options)?;
+
+            // Only reset takes a target
+            if options.start
+                || options.position.is_some()
+                || options.time.is_some()
+                || options.dry_run
+            {
+                return None;
+            }
+
+            Some(Command::Lag {
+                category: category.clone(),
+                identifier: options.identifier,
+            })
+        }
+        "reset" => {
+            let (category, options) = args.split_first()?;
+            let options = parse_options(options)?;
+
+            let to = match (options.start, options.position, options.time) {
+                (true, None, None) => ResetTo::Start,
+                (false, Some(position), None) => ResetTo::GlobalPosition(position),
+                (false, None, Some(time)) => ResetTo::Time(time),
+                _ => return None,
+            };
+
+            Some(Command::Reset {
+                category: category.clone(),
+                identifier: options.identifier,
+                to,
+                dry_run: options.dry_run,
+            })
+        }
+        "message" => match args {
+            [id] => Some(Command::Message {
+                id: Uuid::parse_str(id).ok()?,
+            }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// None when the options are malformed
+fn parse_options(args: &[String]) -> Option<Options> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--identifier" => options.identifier = Some(args.next()?.clone()),
+            "--start" => options.start = true,
+            "--position" => options.position = Some(args.next()?.parse().ok()?),
+            "--time" => {
+                let time = DateTime::parse_from_rfc3339(args.next()?).ok()?;
+
+                options.time = Some(time.with_timezone(&Utc));
+            }
+            "--dry-run" => options.dry_run = true,
+            _ => return None,
+        }
+    }
+
+    Some(options)
+}
+
+fn run(command: Command) -> Result<(), Error> {
+    let mut store = MessageStore::build();
+
+    match command {
+        Command::Lag {
+            category,
+            identifier,
+        } => {
+            let status = consumer_status(&mut store, &category, identifier.as_deref())?;
+
+            print_status(&status);
+        }
+        Command::Reset {
+            category,
+            identifier,
+            to,
+            dry_run,
+        } => {
+            let reset = reset_position(&mut store, &category, identifier.as_deref(), to, dry_run)?;
+
+            print_reset(&reset);
+        }
+        Command::Message { id } => match store.get_by_id(id)? {
+            Some(message) => {
+                // The messages that caused it are listed first
+                for message in store.causation_chain(&message)? {
+                    print_message(&message);
+                }
+            }
+            None => println!("message {} not found", id),
+        },
+    }
+
+    Ok(())
+}
+
+fn print_status(status: &ConsumerStatus) {
+    println!("category:             {}", status.category);
+    println!(
+        "identifier:           {}",
+        status.identifier.as_deref().unwrap_or("(none)")
+    );
+    println!("position stream:      {}", status.position_stream_name);
+    println!("position:             {}", display(status.position));
+    println!(
+        "last global position: {}",
+        display(status.last_global_position)
+    );
+    println!("lag:                  {}", status.lag);
+    println!(
+        "updated:              {}",
+        status
+            .updated
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_else(|| String::from("never"))
+    );
+}
+
+fn print_reset(reset: &Reset) {
+    if reset.dry_run {
+        println!("dry run, the position was not changed");
+    }
+
+    println!("position stream:      {}", reset.position_stream_name);
+    println!("previous position:    {}", display(reset.previous_position));
+    println!("position:             {}", reset.position);
+    println!("messages to process:  {}", reset.messages_to_process);
+}
+
+fn print_message(message: &MessageData) {
+    println!("id:              {}", display_id(message.id));
+    println!(
+        "stream name:     {}",
+        message.stream_name.as_deref().unwrap_or("none")
+    );
+    println!("type:            {}", message.message_type);
+    println!("position:        {}", display(message.position));
+    println!("global position: {}", display(message.global_position));
+    println!(
+        "time:            {}",
+        message
+            .time
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_else(|| String::from("none"))
+    );
+    println!("data:            {}", message.data);
+    println!("metadata:        {}", message.metadata);
+    println!();
+}
+
+fn display_id(id: Option<Uuid>) -> String {
+    id.map(|id| id.to_string())
+        .unwrap_or_else(|| String::from("none"))
+}
+
+fn display(position: Option<i64>) -> String {
+    position
+        .map(|position| position.to_string())
+        .unwrap_or_else(|| String::from("none"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3b3ac7a4-8f85-4e8b-9f2a-3c0a3e2b8f43";
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[test]
+    fn parses_lag() {
+        assert_eq!(
+            Some(Command::Lag {
+                category: String::from("account"),
+                identifier: None,
+            }),
+            parse(&args(&["lag", "account"]))
+        );
+        assert_eq!(
+            Some(Command::Lag {
+                category: String::from("account"),
+                identifier: Some(String::from("worker")),
+            }),
+            parse(&args(&["lag", "account", "--identifier", "worker"]))
+        );
+    }
+
+    #[test]
+    fn rejects_reset_options_for_lag() {
+        assert_eq!(None, parse(&args(&["lag", "account", "--start"])));
+        assert_eq!(None, parse(&args(&["lag", "account", "--position", "5"])));
+        assert_eq!(None, parse(&args(&["lag", "account", "--dry-run"])));
+    }
+
+    #[test]
+    fn parses_reset_to_each_target() {
+        assert_eq!(
+            Some(Command::Reset {
+                category: String::from("account"),
+                identifier: Some(String::from("worker")),
+                to: ResetTo::Start,
+                dry_run: true,
+            }),
+            parse(&args(&[
+                "reset",
+                "account",
+                "--start",
+                "--identifier",
+                "worker",
+                "--dry-run"
+            ]))
+        );
+        assert_eq!(
+            Some(Command::Reset {
+                category: String::from("account"),
+                identifier: None,
+                to: ResetTo::GlobalPosition(42),
+                dry_run: false,
+            }),
+            parse(&args(&["reset", "account", "--position", "42"]))
+        );
+
+        let time = DateTime::parse_from_rfc3339("2222-02-22T02:22:22Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            Some(Command::Reset {
+                category: String::from("account"),
+                identifier: None,
+                to: ResetTo::Time(time),
+                dry_run: false,
+            }),
+            parse(&args(&[
+                "reset",
+                "account",
+                "--time",
+                "2222-02-22T02:22:22Z"
+            ]))
+        );
+    }
+
+    #[test]
+    fn rejects_resets_without_exactly_one_target() {
+        assert_eq!(None, parse(&args(&["reset", "account"])));
+        assert_eq!(
+            None,
+            parse(&args(&[
+                "reset",
+                "account",
+                "--position",
+                "42",
+                "--time",
+                "2222-02-22T02:22:22Z"
+            ]))
+        );
+        assert_eq!(
+            None,
+            parse(&args(&["reset", "account", "--start", "--position", "42"]))
+        );
+    }
+
+    #[test]
+    fn parses_message() {
+        assert_eq!(
+            Some(Command::Message {
+                id: Uuid::parse_str(ID).unwrap(),
+            }),
+            parse(&args(&["message", ID]))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert_eq!(None, parse(&args(&["message"])));
+        assert_eq!(None, parse(&args(&["message", "not-a-uuid"])));
+        assert_eq!(None, parse(&args(&["message", ID, ID])));
+    }
+
+    #[test]
+    fn rejects_missing_arguments() {
+        assert_eq!(None, parse(&args(&[])));
+        assert_eq!(None, parse(&args(&["lag"])));
+        assert_eq!(None, parse(&args(&["reset"])));
+        assert_eq!(None, parse(&args(&["unknown", "account"])));
+    }
+
+    #[test]
+    fn parses_options() {
+        assert_eq!(
+            Some(Options {
+                identifier: Some(String::from("worker")),
+                start: true,
+                position: None,
+                time: None,
+                dry_run: true,
+            }),
+            parse_options(&args(&["--identifier", "worker", "--start", "--dry-run"]))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        assert_eq!(None, parse_options(&args(&["--verbose"])));
+        assert_eq!(None, parse(&args(&["lag", "account", "--verbose"])));
+    }
+
+    #[test]
+    fn rejects_options_missing_their_values() {
+        assert_eq!(None, parse_options(&args(&["--identifier"])));
+        assert_eq!(None, parse_options(&args(&["--position"])));
+        assert_eq!(None, parse_options(&args(&["--time"])));
+    }
+
+    #[test]
+    fn rejects_malformed_option_values() {
+        assert_eq!(None, parse_options(&args(&["--position", "five"])));
+        assert_eq!(None, parse_options(&args(&["--time", "yesterday"])));
+    }
+}
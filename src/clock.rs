@@ -1,5 +1,9 @@
 pub mod controls;
 
+use std::sync::{Arc, Mutex};
+
+use chrono::Duration;
+
 use crate::{DateTime, Utc};
 
 pub trait Clock {
@@ -12,30 +16,91 @@ pub trait Clock {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::clock::{controls, Clock};
-    use chrono::{DateTime, Utc};
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {}
+
+// Always the same time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedClock {
+    now: DateTime<Utc>,
+}
+
+impl FixedClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self { now }
+    }
+}
 
-    struct TestClock {
-        now: DateTime<Utc>,
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.now
     }
+}
+
+// Only moves when told to. Clones share the time, so a test can keep one to
+// advance after handing another to a consumer.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
 
-    impl Clock for TestClock {
-        fn now(&self) -> DateTime<Utc> {
-            self.now
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
         }
     }
 
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+
+        *now += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use crate::clock::{controls, Clock, FixedClock, ManualClock};
+
     #[test]
     fn creates_rfc3339_timestamps() {
         let now = controls::time();
         let expected = controls::rfc3339();
 
-        let clock = TestClock { now };
+        let clock = FixedClock::new(now);
 
         let time = clock.rfc3339();
 
         assert_eq!(expected, time);
     }
+
+    #[test]
+    fn manual_clocks_move_when_advanced() {
+        let clock = ManualClock::new(controls::time());
+        let shared = clock.clone();
+
+        assert_eq!(controls::time(), clock.now());
+
+        shared.advance(Duration::seconds(90));
+
+        assert_eq!(controls::time() + Duration::seconds(90), clock.now());
+
+        shared.set(controls::time());
+
+        assert_eq!(controls::time(), clock.now());
+    }
 }
@@ -0,0 +1,528 @@
+use log::{error, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::consumer::consumer::Stopper;
+use crate::consumer::{BackOff, Consumer, ExponentialBackOff, Interrupt};
+use crate::Error;
+
+const RESTART_LIMIT_DEFAULT: u32 = 5;
+const RESTART_INTERVAL_MILLISECONDS_DEFAULT: u64 = 1000;
+const RESTART_INTERVAL_MAX_MILLISECONDS_DEFAULT: u64 = 30_000;
+const RESTART_RESET_MILLISECONDS_DEFAULT: u64 = 60_000;
+
+// How often the host checks for a signal while its components are running
+const SUPERVISION_INTERVAL_MILLISECONDS: u64 = 100;
+
+pub trait Component {
+    fn start(&mut self) -> Result<(), Error>;
+    fn stopper(&self) -> Box<dyn Stopper + Send>;
+}
+
+impl<B: BackOff + 'static> Component for Consumer<B> {
+    fn start(&mut self) -> Result<(), Error> {
+        Consumer::start(self)?;
+        Ok(())
+    }
+
+    fn stopper(&self) -> Box<dyn Stopper + Send> {
+        Box::new(Consumer::stopper(self))
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Settings {
+    pub restart_limit: Option<u32>,                     // restart_limit
+    pub restart_interval_milliseconds: Option<u64>,     // restart_interval_milliseconds
+    pub restart_interval_max_milliseconds: Option<u64>, // restart_interval_max_milliseconds
+    pub restart_reset_milliseconds: Option<u64>,        // restart_reset_milliseconds
+}
+
+type Factory = Box<dyn Fn() -> Result<Box<dyn Component>, Error> + Send>;
+
+struct Registration {
+    name: String,
+    factory: Factory,
+}
+
+// Runs every registered component on its own thread until SIGINT or SIGTERM
+// is received, restarting components that fail with a back-off and giving up
+// on all of them once any component fails more than the restart limit. A
+// component that runs for the restart reset interval before failing starts
+// over with a clean slate. A second signal exits the process at once.
+pub struct ComponentHost {
+    name: String,
+    settings: Settings,
+    registrations: Vec<Registration>,
+    stopping: Arc<AtomicBool>,
+    // Set only by signals, so a stop or a failure doesn't make the first
+    // signal the second
+    signalled: Arc<AtomicBool>,
+}
+
+impl ComponentHost {
+    pub fn new(name: &str) -> Self {
+        Self::with_settings(name, Settings::default())
+    }
+
+    pub fn with_settings(name: &str, settings: Settings) -> Self {
+        Self {
+            name: String::from(name),
+            settings,
+            registrations: vec![],
+            stopping: Arc::new(AtomicBool::new(false)),
+            signalled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // The factory is called again to build a fresh component on every restart
+    pub fn register<C, F>(&mut self, name: &str, factory: F)
+    where
+        C: Component + 'static,
+        F: Fn() -> Result<C, Error> + Send + 'static,
+    {
+        let factory: Factory = Box::new(move || {
+            let component: Box<dyn Component> = Box::new(factory()?);
+            Ok(component)
+        });
+
+        self.registrations.push(Registration {
+            name: String::from(name),
+            factory,
+        });
+    }
+
+    pub fn stopper(&self) -> impl Stopper {
+        HostStopper {
+            stopping: self.stopping.clone(),
+        }
+    }
+
+    pub fn run(self) -> Result<(), Error> {
+        // The conditional shutdown is registered first so it only sees the
+        // flag set by an earlier signal
+        let mut signals = vec![];
+
+        for signal in [SIGINT, SIGTERM].iter() {
+            signals.push(signal_hook::flag::register_conditional_shutdown(
+                *signal,
+                1,
+                self.signalled.clone(),
+            )?);
+            signals.push(signal_hook::flag::register(
+                *signal,
+                self.signalled.clone(),
+            )?);
+        }
+
+        let result = self.supervise();
+
+        for signal in signals {
+            signal_hook::low_level::unregister(signal);
+        }
+
+        result
+    }
+
+    fn supervise(self) -> Result<(), Error> {
+        info!("Starting {}", self.name);
+
+        let (sender, receiver) = mpsc::channel();
+        let mut components = vec![];
+
+        for registration in self.registrations {
+            let running = Running::new(&registration.name);
+            let worker = Worker {
+                registration,
+                settings: self.settings.clone(),
+                stopping: self.stopping.clone(),
+                current: running.current.clone(),
+                interrupt: running.interrupt.clone(),
+            };
+            let sender = sender.clone();
+
+            let handle = thread::spawn(move || {
+                let name = worker.registration.name.clone();
+                let result = worker.run();
+                let _ = sender.send((name, result));
+            });
+
+            components.push((running, handle));
+        }
+
+        drop(sender);
+
+        let interval = Duration::from_millis(SUPERVISION_INTERVAL_MILLISECONDS);
+        let mut result = Ok(());
+
+        while !self.stopping.load(Ordering::SeqCst) && !self.signalled.load(Ordering::SeqCst) {
+            match receiver.recv_timeout(interval) {
+                Ok((name, Ok(()))) => info!("{} finished", name),
+                Ok((name, Err(e))) => {
+                    error!("{} failed, stopping {}: {}", name, self.name, e);
+                    result = Err(e);
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        info!("Stopping {}", self.name);
+
+        self.stopping.store(true, Ordering::SeqCst);
+
+        for (running, _) in components.iter() {
+            running.stop();
+        }
+
+        for (running, handle) in components {
+            join(&running.name, handle);
+        }
+
+        info!("Stopped {}", self.name);
+
+        result
+    }
+}
+
+// Runs the components registered by `register` and exits the process with a
+// non-zero status when one of them fails fatally
+pub fn start<F>(name: &str, register: F)
+where
+    F: FnOnce(&mut ComponentHost),
+{
+    let mut host = ComponentHost::new(name);
+
+    register(&mut host);
+
+    if let Err(e) = host.run() {
+        error!("{} failed: {}", name, e);
+        process::exit(1);
+    }
+}
+
+type CurrentStopper = Arc<Mutex<Option<Box<dyn Stopper + Send>>>>;
+
+struct Running {
+    name: String,
+    current: CurrentStopper,
+    interrupt: Interrupt,
+}
+
+impl Running {
+    fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            current: Arc::new(Mutex::new(None)),
+            interrupt: Interrupt::default(),
+        }
+    }
+
+    fn stop(&self) {
+        self.interrupt.interrupt();
+
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(stopper) = current.as_mut() {
+            stopper.stop();
+        }
+    }
+}
+
+struct Worker {
+    registration: Registration,
+    settings: Settings,
+    stopping: Arc<AtomicBool>,
+    current: CurrentStopper,
+    interrupt: Interrupt,
+}
+
+impl Worker {
+    fn run(&self) -> Result<(), Error> {
+        let name = &self.registration.name;
+        let restart_limit = self.settings.restart_limit.unwrap_or(RESTART_LIMIT_DEFAULT);
+        let mut back_off = ExponentialBackOff::new(
+            self.settings
+                .restart_interval_milliseconds
+                .unwrap_or(RESTART_INTERVAL_MILLISECONDS_DEFAULT),
+            self.settings
+                .restart_interval_max_milliseconds
+                .unwrap_or(RESTART_INTERVAL_MAX_MILLISECONDS_DEFAULT),
+        );
+        back_off.interrupt_with(self.interrupt.clone());
+        let restart_reset = Duration::from_millis(
+            self.settings
+                .restart_reset_milliseconds
+                .unwrap_or(RESTART_RESET_MILLISECONDS_DEFAULT),
+        );
+        let mut restarts = 0;
+
+        loop {
+            let started = Instant::now();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| self.start()))
+                .unwrap_or_else(|_| Err(Error::ComponentPanicked(name.clone())));
+
+            if result.is_err() && started.elapsed() >= restart_reset {
+                restarts = 0;
+                back_off.reset();
+            }
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if self.stopping.load(Ordering::SeqCst) => {
+                    warn!("{} failed while stopping: {}", name, e);
+                    return Ok(());
+                }
+                Err(e) if restarts >= restart_limit => return Err(e),
+                Err(e) => {
+                    restarts += 1;
+                    warn!(
+                        "Restarting {} ({}/{}): {}",
+                        name, restarts, restart_limit, e
+                    );
+                    back_off.wait(0);
+                }
+            }
+        }
+    }
+
+    fn start(&self) -> Result<(), Error> {
+        let mut component = (self.registration.factory)()?;
+
+        // The stopper is in place before checking whether the host is stopping,
+        // so a stop can't slip in between
+        *self.current.lock().unwrap_or_else(PoisonError::into_inner) = Some(component.stopper());
+
+        if self.stopping.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        component.start()
+    }
+}
+
+fn join(name: &str, handle: JoinHandle<()>) {
+    if handle.join().is_err() {
+        error!("{} thread panicked", name);
+    }
+}
+
+#[derive(Debug)]
+pub struct HostStopper {
+    stopping: Arc<AtomicBool>,
+}
+
+impl Stopper for HostStopper {
+    fn stop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use crate::consumer::{Consumer, Settings as ConsumerSettings};
+    use crate::message_store::controls;
+    use crate::stream_name;
+
+    use super::*;
+
+    // Signals reach every host in the process, so hosts run one at a time
+    static HOSTS: Mutex<()> = Mutex::new(());
+
+    fn run(host: ComponentHost) -> Result<(), Error> {
+        let _running = HOSTS.lock().unwrap_or_else(PoisonError::into_inner);
+
+        host.run()
+    }
+
+    struct Failing {
+        starts: Arc<AtomicUsize>,
+    }
+
+    impl Component for Failing {
+        fn start(&mut self) -> Result<(), Error> {
+            self.starts.fetch_add(1, Ordering::SeqCst);
+            Err(Error::MissingField("someField"))
+        }
+
+        fn stopper(&self) -> Box<dyn Stopper + Send> {
+            Box::new(HostStopper {
+                stopping: Arc::new(AtomicBool::new(false)),
+            })
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            restart_limit: Some(2),
+            restart_interval_milliseconds: Some(1),
+            restart_interval_max_milliseconds: Some(1),
+            restart_reset_milliseconds: Some(1000),
+        }
+    }
+
+    fn consumer() -> Result<Consumer<crate::consumer::SimpleBackOff>, Error> {
+        let settings = ConsumerSettings {
+            poll_interval_milliseconds: Some(10),
+            ..Default::default()
+        };
+
+        Ok(Consumer::new(
+            stream_name::controls::unique_category(),
+            controls::message_store(),
+            settings,
+        ))
+    }
+
+    #[test]
+    fn stops_its_consumers_when_stopped() {
+        let mut host = ComponentHost::with_settings("test-host", settings());
+        host.register("first", consumer);
+        host.register("second", consumer);
+
+        let mut stopper = host.stopper();
+        let stopping = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            stopper.stop();
+        });
+
+        assert!(run(host).is_ok());
+
+        stopping.join().unwrap();
+    }
+
+    #[test]
+    fn restarts_a_failed_component_until_the_restart_limit() {
+        let starts = Arc::new(AtomicUsize::new(0));
+        let counter = starts.clone();
+
+        let mut host = ComponentHost::with_settings("test-host", settings());
+        host.register("failing", move || {
+            Ok(Failing {
+                starts: counter.clone(),
+            })
+        });
+
+        let result = run(host);
+
+        assert!(matches!(result, Err(Error::MissingField(_))));
+        assert_eq!(3, starts.load(Ordering::SeqCst));
+    }
+
+    struct FailingLater {
+        starts: Arc<AtomicUsize>,
+        runs_for: Duration,
+    }
+
+    impl Component for FailingLater {
+        fn start(&mut self) -> Result<(), Error> {
+            self.starts.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(self.runs_for);
+            Err(Error::MissingField("someField"))
+        }
+
+        fn stopper(&self) -> Box<dyn Stopper + Send> {
+            Box::new(HostStopper {
+                stopping: Arc::new(AtomicBool::new(false)),
+            })
+        }
+    }
+
+    #[test]
+    fn keeps_restarting_a_component_that_runs_for_the_restart_reset_interval() {
+        let starts = Arc::new(AtomicUsize::new(0));
+        let counter = starts.clone();
+
+        let settings = Settings {
+            restart_reset_milliseconds: Some(10),
+            ..settings()
+        };
+        let mut host = ComponentHost::with_settings("test-host", settings);
+        host.register("failing-later", move || {
+            Ok(FailingLater {
+                starts: counter.clone(),
+                runs_for: Duration::from_millis(20),
+            })
+        });
+
+        let mut stopper = host.stopper();
+        let observed = starts.clone();
+        let stopping = thread::spawn(move || {
+            while observed.load(Ordering::SeqCst) <= 5 {
+                thread::sleep(Duration::from_millis(5));
+            }
+            stopper.stop();
+        });
+
+        assert!(run(host).is_ok());
+        assert!(starts.load(Ordering::SeqCst) > 5);
+
+        stopping.join().unwrap();
+    }
+
+    // Takes a while to stop, like a consumer writing its position
+    struct SlowToStop {
+        stopping: Arc<AtomicBool>,
+    }
+
+    impl Component for SlowToStop {
+        fn start(&mut self) -> Result<(), Error> {
+            while !self.stopping.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(10));
+            }
+
+            thread::sleep(Duration::from_millis(500));
+            Ok(())
+        }
+
+        fn stopper(&self) -> Box<dyn Stopper + Send> {
+            Box::new(HostStopper {
+                stopping: self.stopping.clone(),
+            })
+        }
+    }
+
+    #[test]
+    fn a_signal_after_a_stop_is_not_the_second_signal() {
+        let mut host = ComponentHost::with_settings("test-host", settings());
+        host.register("slow-to-stop", || {
+            Ok(SlowToStop {
+                stopping: Arc::new(AtomicBool::new(false)),
+            })
+        });
+
+        let mut stopper = host.stopper();
+        let running = thread::spawn(move || run(host));
+
+        thread::sleep(Duration::from_millis(200));
+        stopper.stop();
+        thread::sleep(Duration::from_millis(100));
+
+        // Exits the test process when taken for a second signal
+        signal_hook::low_level::raise(SIGTERM).unwrap();
+
+        assert!(running.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn restarts_a_panicked_component() {
+        let mut host = ComponentHost::with_settings("test-host", settings());
+        host.register("panicking", || -> Result<Failing, Error> {
+            panic!("component panicked")
+        });
+
+        let result = run(host);
+
+        assert!(matches!(result, Err(Error::ComponentPanicked(_))));
+    }
+}
@@ -1,14 +1,25 @@
+pub mod back_off;
 pub mod consumer;
 pub mod controls;
 mod core;
 pub mod entity_cache;
 pub mod entity_store;
+pub mod error_policy;
+pub mod handler;
+pub mod notify;
 pub mod position_store;
+pub mod retry;
 pub mod write_message;
 
-pub use self::consumer::Consumer;
+pub use self::back_off::{BackOff, ExponentialBackOff, Interrupt, SimpleBackOff};
+pub use self::consumer::{Consumer, Summary};
 pub use self::core::Settings;
 pub use self::entity_cache::EntityCache;
 pub use self::entity_store::EntityStore;
-pub use self::position_store::PositionStore;
+pub use self::error_policy::ErrorPolicy;
+pub use self::handler::{Context, Handler};
+pub use self::position_store::{
+    consumer_status, reset_position, ConsumerStatus, PositionStore, Reset, ResetTo,
+};
+pub use self::retry::RetryOnConflict;
 pub use self::write_message::WriteMessage;
@@ -0,0 +1,236 @@
+use rand::{thread_rng, Rng};
+
+use std::cmp;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+pub trait BackOff {
+    fn wait(&mut self, messages_processed: u64);
+
+    // Called with the interrupt of whatever waits on the back-off, which is
+    // set when it's stopped. Back-offs that ignore it keep a stopping
+    // consumer waiting until the end of the interval.
+    fn interrupt_with(&mut self, _interrupt: Interrupt) {}
+}
+
+// Wakes a waiting back-off once it's set, and keeps it from waiting again
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Interrupt {
+    pub fn interrupt(&self) {
+        let (interrupted, condvar) = &*self.state;
+
+        *interrupted.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        condvar.notify_all();
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        let (interrupted, _) = &*self.state;
+
+        *interrupted.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Sleeps for the duration or until interrupted, whichever is sooner
+    pub fn sleep(&self, duration: Duration) {
+        let (interrupted, condvar) = &*self.state;
+        let guard = interrupted.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let _ = condvar
+            .wait_timeout_while(guard, duration, |interrupted| !*interrupted)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+pub struct SimpleBackOff {
+    poll_interval_milliseconds: u64,
+    interrupt: Interrupt,
+}
+
+impl SimpleBackOff {
+    pub fn new(poll_interval_milliseconds: u64) -> Self {
+        Self {
+            poll_interval_milliseconds,
+            interrupt: Interrupt::default(),
+        }
+    }
+}
+
+impl BackOff for SimpleBackOff {
+    fn wait(&mut self, messages_processed: u64) {
+        if messages_processed == 0 {
+            self.interrupt
+                .sleep(Duration::from_millis(self.poll_interval_milliseconds));
+        }
+    }
+
+    fn interrupt_with(&mut self, interrupt: Interrupt) {
+        self.interrupt = interrupt;
+    }
+}
+
+// Doubles the interval after every empty poll up to the maximum, and starts
+// over from the initial interval as soon as a poll processes any messages
+pub struct ExponentialBackOff {
+    initial_milliseconds: u64,
+    max_milliseconds: u64,
+    jitter: bool,
+    interval_milliseconds: u64,
+    interrupt: Interrupt,
+}
+
+impl ExponentialBackOff {
+    pub fn new(initial_milliseconds: u64, max_milliseconds: u64) -> Self {
+        let initial_milliseconds = cmp::max(initial_milliseconds, 1);
+
+        Self {
+            initial_milliseconds,
+            max_milliseconds: cmp::max(max_milliseconds, initial_milliseconds),
+            jitter: false,
+            interval_milliseconds: initial_milliseconds,
+            interrupt: Interrupt::default(),
+        }
+    }
+
+    // Sleeps somewhere between half and all of the interval so idle
+    // consumers started together don't keep polling in lockstep
+    pub fn jittered(initial_milliseconds: u64, max_milliseconds: u64) -> Self {
+        Self {
+            jitter: true,
+            ..Self::new(initial_milliseconds, max_milliseconds)
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.interval_milliseconds = self.initial_milliseconds;
+    }
+
+    fn next_sleep(&mut self) -> Duration {
+        let interval = self.interval_milliseconds;
+
+        self.interval_milliseconds = cmp::min(interval.saturating_mul(2), self.max_milliseconds);
+
+        let milliseconds = if self.jitter {
+            let half = interval / 2;
+            half + thread_rng().gen_range(0..=interval - half)
+        } else {
+            interval
+        };
+
+        Duration::from_millis(milliseconds)
+    }
+}
+
+impl BackOff for ExponentialBackOff {
+    fn wait(&mut self, messages_processed: u64) {
+        if messages_processed > 0 {
+            self.reset();
+            return;
+        }
+
+        let sleep = self.next_sleep();
+
+        self.interrupt.sleep(sleep);
+    }
+
+    fn interrupt_with(&mut self, interrupt: Interrupt) {
+        self.interrupt = interrupt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn exponential_back_off_doubles_up_to_the_max() {
+        let mut back_off = ExponentialBackOff::new(100, 500);
+
+        let sleeps: Vec<Duration> = (0..5).map(|_| back_off.next_sleep()).collect();
+
+        let expected: Vec<Duration> = vec![100, 200, 400, 500, 500]
+            .into_iter()
+            .map(Duration::from_millis)
+            .collect();
+
+        assert_eq!(expected, sleeps);
+    }
+
+    #[test]
+    fn exponential_back_off_resets_after_messages_are_processed() {
+        let mut back_off = ExponentialBackOff::new(100, 500);
+
+        back_off.next_sleep();
+        back_off.next_sleep();
+        back_off.wait(1);
+
+        assert_eq!(Duration::from_millis(100), back_off.next_sleep());
+    }
+
+    #[test]
+    fn jittered_back_off_sleeps_between_half_and_all_of_the_interval() {
+        let mut back_off = ExponentialBackOff::jittered(100, 100);
+
+        for _ in 0..100 {
+            let sleep = back_off.next_sleep();
+
+            assert!(sleep >= Duration::from_millis(50));
+            assert!(sleep <= Duration::from_millis(100));
+        }
+    }
+
+    fn interrupt_after(back_off: &mut dyn BackOff, delay: Duration) -> thread::JoinHandle<()> {
+        let interrupt = Interrupt::default();
+        back_off.interrupt_with(interrupt.clone());
+
+        thread::spawn(move || {
+            thread::sleep(delay);
+            interrupt.interrupt();
+        })
+    }
+
+    #[test]
+    fn interrupting_a_simple_back_off_cuts_its_wait_short() {
+        let mut back_off = SimpleBackOff::new(10_000);
+        let interrupting = interrupt_after(&mut back_off, Duration::from_millis(50));
+
+        let start = Instant::now();
+        back_off.wait(0);
+
+        interrupting.join().unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn interrupting_an_exponential_back_off_cuts_its_wait_short() {
+        let mut back_off = ExponentialBackOff::new(10_000, 10_000);
+        let interrupting = interrupt_after(&mut back_off, Duration::from_millis(50));
+
+        let start = Instant::now();
+        back_off.wait(0);
+
+        interrupting.join().unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn interrupted_back_offs_do_not_wait() {
+        let interrupt = Interrupt::default();
+        interrupt.interrupt();
+
+        let mut back_off = SimpleBackOff::new(10_000);
+        back_off.interrupt_with(interrupt);
+
+        let start = Instant::now();
+        back_off.wait(0);
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
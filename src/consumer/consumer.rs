@@ -1,100 +1,283 @@
-use log::warn;
-use serde::{de::DeserializeOwned, Serialize};
+use log::{error, info, warn};
 
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::sync::{Arc, Mutex};
-use std::{thread, time::Duration};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::consumer::position_store::PositionStore;
+use crate::consumer::back_off::{BackOff, Interrupt, SimpleBackOff};
+use crate::consumer::core::not_a_category;
+use crate::consumer::error_policy::{
+    dead_letter_category, dead_letter_identifier, dead_letter_stream_name, DeadLetter, ErrorPolicy,
+};
+use crate::consumer::handler::{Context, FnHandler, Handler, HandlerFn};
+use crate::consumer::notify::NotifyBackOff;
+use crate::consumer::position_store::{PositionStore, Positions};
 use crate::consumer::Settings;
-use crate::message_store::{MessageData, MessageStore};
-use crate::messaging::{Message, MessageType};
-use crate::Error;
+use crate::message_store::all::get_all;
+use crate::message_store::{get, Filter, MessageData, MessageStore, Read, ALL};
+use crate::messaging::{Message, MessageType, Write};
+#[cfg(feature = "tracing")]
+use crate::trace;
+use crate::{metrics, Clock, Error, SystemClock};
 
 const POLL_INTERVAL_MILLISECONDS_DEFAULT: u64 = 1000; // TODO: make sure this works
-const STARTING_POSITION: i64 = 0; // TODO: verify its 0 or -1 for subscription
+const POSITION_UPDATE_INTERVAL_DEFAULT: i64 = 100;
+const STOP_WAIT_INTERVAL_MILLISECONDS: u64 = 10;
+
+// Positions are the global position of the last message handled, and
+// categories start at global position 1
+const STARTING_POSITION: i64 = 0;
+
+#[derive(Clone)]
+struct Registration {
+    handler: usize, // index into the consumer's handlers
+    policy: ErrorPolicy,
+}
 
 pub struct Consumer<B: BackOff> {
     category: String,
     settings: Settings,
     store: MessageStore,
     current_position: i64,
+    messages_since_position_update: i64,
     back_off: B,
-    handlers: HashMap<String, fn(message_data: MessageData) -> Result<(), Error>>,
-    should_continue: Arc<Mutex<bool>>,
+    clock: Box<dyn Clock + Send>,
+    handlers: Vec<Box<dyn Handler + Send>>,
+    registrations: HashMap<String, Registration>,
+    should_continue: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    interrupt: Interrupt,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub messages_processed: u64,
+    pub position: i64,
 }
 
 impl Consumer<SimpleBackOff> {
     pub fn new(category: String, store: MessageStore, settings: Settings) -> Self {
-        let poll_interval_milliseconds = settings
-            .poll_interval_milliseconds
-            .unwrap_or(POLL_INTERVAL_MILLISECONDS_DEFAULT);
+        let poll_interval_milliseconds = poll_interval_milliseconds(&settings);
+        let back_off = SimpleBackOff::new(poll_interval_milliseconds);
+
+        Self::with_back_off(category, store, settings, back_off)
+    }
+}
+
+impl Consumer<NotifyBackOff> {
+    // Waits for a notification of new messages in the category instead of
+    // sleeping, polling anyway when none arrives within the poll interval
+    pub fn with_notifications(
+        category: String,
+        store: MessageStore,
+        settings: Settings,
+    ) -> Result<Self, Error> {
+        let poll_interval_milliseconds = poll_interval_milliseconds(&settings);
+        let back_off = NotifyBackOff::build(&category, poll_interval_milliseconds)?;
+
+        Ok(Self::with_back_off(category, store, settings, back_off))
+    }
+}
+
+impl<B: BackOff> Consumer<B> {
+    pub fn with_back_off(
+        category: String,
+        store: MessageStore,
+        settings: Settings,
+        mut back_off: B,
+    ) -> Self {
+        let interrupt = Interrupt::default();
+        back_off.interrupt_with(interrupt.clone());
+
         Self {
             category,
             settings,
             store,
             current_position: STARTING_POSITION,
-            back_off: SimpleBackOff {
-                poll_interval_milliseconds,
-            },
-            handlers: HashMap::new(),
-            should_continue: Arc::new(Mutex::new(true)),
+            messages_since_position_update: 0,
+            back_off,
+            clock: Box::new(SystemClock),
+            handlers: vec![],
+            registrations: HashMap::new(),
+            should_continue: Arc::new(AtomicBool::new(true)),
+            running: Arc::new(AtomicBool::new(false)),
+            interrupt,
+        }
+    }
+
+    // The time handlers are given, which is also stamped on what the consumer
+    // and its handlers write
+    pub fn with_clock<C: Clock + Clone + Send + 'static>(mut self, clock: C) -> Self {
+        self.store.clock = Box::new(clock.clone());
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn add_handler<T: MessageType + 'static>(&mut self, handler: HandlerFn) {
+        self.add_handler_with_policy::<T>(handler, ErrorPolicy::default());
+    }
+
+    pub fn add_handler_with_policy<T: MessageType + 'static>(
+        &mut self,
+        handler: HandlerFn,
+        policy: ErrorPolicy,
+    ) {
+        self.register_with_policy(FnHandler::<T>::new(handler), policy);
+    }
+
+    pub fn register<H: Handler + Send + 'static>(&mut self, handler: H) {
+        self.register_with_policy(handler, ErrorPolicy::default());
+    }
+
+    // Routes every message type the handler lists to it, taking over from
+    // any handler registered for those types before
+    pub fn register_with_policy<H: Handler + Send + 'static>(
+        &mut self,
+        handler: H,
+        policy: ErrorPolicy,
+    ) {
+        let index = self.handlers.len();
+
+        for message_type in handler.message_types() {
+            let registration = Registration {
+                handler: index,
+                policy: policy.clone(),
+            };
+
+            if self
+                .registrations
+                .insert(message_type.clone(), registration)
+                .is_some()
+            {
+                warn!("Re-assigning handler for {}", message_type);
+            }
+        }
+
+        self.handlers.push(Box::new(handler));
+    }
+
+    pub fn dead_letter_stream_name(&self) -> Option<String> {
+        dead_letter_stream_name(&self.category, self.settings.identifier.as_deref())
+    }
+
+    // Hands the messages dead lettered since the last replay back to their
+    // handler. Messages that fail again are dealt with by the handler's
+    // policy, so they may be dead lettered again, and are replayed next time.
+    // The position of the last dead letter replayed is written after each
+    // one, so a replay that stops part way resumes where it left off.
+    pub fn replay_dead_letters(&mut self) -> Result<u64, Error> {
+        let stream_name = self
+            .dead_letter_stream_name()
+            .ok_or_else(|| not_a_category(&self.category))?;
+        let category =
+            dead_letter_category(&self.category).ok_or_else(|| not_a_category(&self.category))?;
+        let identifier = String::from(dead_letter_identifier(self.settings.identifier.as_deref()));
+
+        let position = Positions::new(&category, &mut self.store)
+            .get_last(Some(&identifier))?
+            .map_or(0, |position| position + 1);
+
+        let dead_letters = self
+            .store
+            .read_from(&stream_name, position)
+            .collect::<Result<Vec<MessageData>, Error>>()?;
+
+        let mut replayed = 0;
+
+        for message_data in dead_letters {
+            let position = message_data.position;
+            let dead_letter: Message<DeadLetter> = Message::try_from(message_data)?;
+
+            self.dispatch(dead_letter.into_inner().message)?;
+            replayed += 1;
+
+            if let Some(position) = position {
+                Positions::new(&category, &mut self.store).update(Some(&identifier), position)?;
+            }
         }
+
+        Ok(replayed)
     }
 
-    // pub fn add_handler<T>(&mut self, handler: fn(message_data: MessageData) -> Result<(), Error>)
-    // where
-    //     T: MessageType + Default + Serialize + DeserializeOwned,
-    //     Message<T>: TryFrom<MessageData>,
-    // {
-    //     self.handlers
-    //         .entry(T::message_type())
-    //         .and_modify(|current_handler| {
-    //             warn!("Re-assinging handler for {}", T::message_type());
-    //             *current_handler = handler;
-    //         })
-    //         .or_insert(handler);
-    // }
+    // Runs until stopped, finishing the message in hand and writing the
+    // position of the last message handled before returning
+    pub fn start(&mut self) -> Result<Summary, Error> {
+        self.running.store(true, Ordering::SeqCst);
+
+        let result = self.poll_continuously();
 
-    pub fn start(&mut self) -> Result<(), Error> {
-        self.poll_continuously()
+        self.running.store(false, Ordering::SeqCst);
+
+        result
     }
 
-    pub fn stopper(&self) -> impl Stopper {
+    pub fn stopper(&self) -> ConsumerStopper {
         ConsumerStopper {
             should_continue: self.should_continue.clone(),
+            running: self.running.clone(),
+            interrupt: self.interrupt.clone(),
         }
     }
 
-    fn poll_continuously(&mut self) -> Result<(), Error> {
+    fn poll_continuously(&mut self) -> Result<Summary, Error> {
+        self.check_condition()?;
         self.load_position()?;
 
-        let lock = self
-            .should_continue
-            .lock()
-            .map_err(|_| Error::ConsumerError)?;
-        let mut should_continue = *lock;
+        let mut messages_processed = 0;
+
+        while self.should_continue() {
+            let processed = match self.poll() {
+                Ok(processed) => processed,
+                Err(e) => {
+                    self.flush_position()?;
+                    return Err(e);
+                }
+            };
+
+            messages_processed += processed;
+
+            if metrics::ENABLED {
+                self.record_lag();
+            }
+
+            if self.should_continue() {
+                self.back_off.wait(processed);
+            }
+        }
+
+        self.flush_position()?;
 
-        // So that poll can be called
-        drop(lock);
+        let summary = Summary {
+            messages_processed,
+            position: self.current_position,
+        };
 
-        while should_continue {
-            let messages_processed = self.poll()?;
+        info!(
+            "Stopped consuming {} after {} messages at global position {}",
+            self.category, summary.messages_processed, summary.position
+        );
 
-            self.back_off.wait(messages_processed);
+        Ok(summary)
+    }
 
-            // lock and check again
-            let lock = self
-                .should_continue
-                .lock()
-                .map_err(|_| Error::ConsumerError)?;
-            should_continue = *lock;
+    // Fails at start rather than on the first poll when the server won't
+    // apply the consumer's condition
+    fn check_condition(&mut self) -> Result<(), Error> {
+        if self.settings.condition.is_some() && !get::sql_condition_enabled(&mut self.store.client)?
+        {
+            return Err(Error::SqlConditionDisabled);
         }
 
         Ok(())
     }
 
+    fn should_continue(&self) -> bool {
+        self.should_continue.load(Ordering::SeqCst)
+    }
+
     fn load_position(&mut self) -> Result<(), Error> {
         let last_position = self.get_last(self.settings.identifier.clone().as_deref())?;
         self.current_position = last_position.unwrap_or(STARTING_POSITION);
@@ -102,10 +285,214 @@ impl Consumer<SimpleBackOff> {
     }
 
     fn poll(&mut self) -> Result<u64, Error> {
-        Ok(todo!())
+        let settings = (&self.settings).into();
+        let position = Some(self.current_position + 1);
+
+        let messages = if self.category == ALL {
+            let filter = self.filter();
+
+            get_all(&mut self.store.client, &settings, &filter, position)
+        } else {
+            get::get(&mut self.store.client, &settings, &self.category, position)
+        }?;
+
+        let mut messages_processed = 0;
+
+        for message_data in messages {
+            if !self.should_continue() {
+                break;
+            }
+
+            let global_position = message_data.global_position;
+
+            self.dispatch(message_data)?;
+
+            if let Some(position) = global_position {
+                self.current_position = position;
+            }
+
+            messages_processed += 1;
+            self.messages_since_position_update += 1;
+
+            if self.messages_since_position_update >= self.position_update_interval() {
+                self.update_position()?;
+            }
+        }
+
+        Ok(messages_processed)
+    }
+
+    fn position_update_interval(&self) -> i64 {
+        self.settings
+            .position_update_interval
+            .unwrap_or(POSITION_UPDATE_INTERVAL_DEFAULT)
+    }
+
+    fn update_position(&mut self) -> Result<(), Error> {
+        let identifier = self.settings.identifier.clone();
+        let position = self.current_position;
+
+        self.update(identifier.as_deref(), position)?;
+        self.messages_since_position_update = 0;
+
+        Ok(())
+    }
+
+    fn flush_position(&mut self) -> Result<(), Error> {
+        if self.messages_since_position_update > 0 {
+            self.update_position()?;
+        }
+
+        Ok(())
+    }
+
+    // Consumers of the whole store only read the messages they handle
+    fn filter(&self) -> Filter {
+        Filter {
+            message_types: Some(self.registrations.keys().cloned().collect()),
+            categories: self.settings.categories.clone(),
+        }
+    }
+
+    fn dispatch(&mut self, message_data: MessageData) -> Result<(), Error> {
+        let registration = match self.registrations.get(&message_data.message_type) {
+            Some(registration) => registration.clone(),
+            None => return Ok(()),
+        };
+
+        match self.handle(registration.handler, message_data.clone()) {
+            Ok(()) => Ok(()),
+            Err(e) => self.recover(message_data, registration.handler, &registration.policy, e),
+        }
+    }
+
+    fn handle(&mut self, handler: usize, message_data: MessageData) -> Result<(), Error> {
+        #[cfg(feature = "tracing")]
+        let _trace = trace::handling(&self.category, &message_data);
+
+        let message_type = message_data.message_type.clone();
+        let mut context = Context {
+            store: &mut self.store,
+            clock: self.clock.as_ref(),
+            category: &self.category,
+            identifier: self.settings.identifier.as_deref(),
+            position: message_data.global_position,
+        };
+
+        self.handlers[handler].handle(message_data, &mut context)?;
+
+        metrics::handled(&self.category, &message_type);
+
+        Ok(())
+    }
+
+    fn record_lag(&mut self) {
+        match get::last_global_position(&mut self.store.client, &self.category) {
+            Ok(last) => {
+                let lag = last.unwrap_or(STARTING_POSITION) - self.current_position;
+
+                metrics::consumer_lag(
+                    &self.category,
+                    self.settings.identifier.as_deref(),
+                    lag.max(0),
+                );
+            }
+            Err(e) => warn!("Could not measure the lag of {}: {}", self.category, e),
+        }
+    }
+
+    fn recover(
+        &mut self,
+        message_data: MessageData,
+        handler: usize,
+        policy: &ErrorPolicy,
+        error: Error,
+    ) -> Result<(), Error> {
+        match policy {
+            ErrorPolicy::Stop => {
+                error!(
+                    "Stopping, {} at global position {:?} failed: {}",
+                    message_data.message_type, message_data.global_position, error
+                );
+                Err(error)
+            }
+            ErrorPolicy::Skip => {
+                warn!(
+                    "Skipping {} at global position {:?}: {}",
+                    message_data.message_type, message_data.global_position, error
+                );
+                Ok(())
+            }
+            ErrorPolicy::DeadLetter => self.dead_letter(message_data, error),
+            ErrorPolicy::Retry {
+                retries,
+                interval_milliseconds,
+                then,
+            } => {
+                let mut error = error;
+                let mut interval = *interval_milliseconds;
+
+                for attempt in 1..=*retries {
+                    warn!(
+                        "Retrying {} at global position {:?} ({}/{}): {}",
+                        message_data.message_type,
+                        message_data.global_position,
+                        attempt,
+                        retries,
+                        error
+                    );
+
+                    self.interrupt.sleep(Duration::from_millis(interval));
+                    interval = interval.saturating_mul(2);
+
+                    // Gives up without handling the message, so it's handled
+                    // again the next time the consumer starts
+                    if !self.should_continue() {
+                        warn!(
+                            "Stopped while retrying {} at global position {:?}",
+                            message_data.message_type, message_data.global_position
+                        );
+                        return Err(error);
+                    }
+
+                    match self.handle(handler, message_data.clone()) {
+                        Ok(()) => return Ok(()),
+                        Err(e) => error = e,
+                    }
+                }
+
+                self.recover(message_data, handler, then, error)
+            }
+        }
+    }
+
+    fn dead_letter(&mut self, message_data: MessageData, error: Error) -> Result<(), Error> {
+        let stream_name = self
+            .dead_letter_stream_name()
+            .ok_or_else(|| not_a_category(&self.category))?;
+
+        warn!(
+            "Dead lettering {} at global position {:?} to {}: {}",
+            message_data.message_type, message_data.global_position, stream_name, error
+        );
+
+        let dead_letter = Message::from_t(DeadLetter {
+            message: message_data,
+            error: error.to_string(),
+        });
+
+        self.store.write(&dead_letter, &stream_name, None)?;
+
+        Ok(())
     }
 }
 
+fn poll_interval_milliseconds(settings: &Settings) -> u64 {
+    settings
+        .poll_interval_milliseconds
+        .unwrap_or(POLL_INTERVAL_MILLISECONDS_DEFAULT)
+}
+
 impl<B: BackOff> PositionStore for Consumer<B> {
     fn get_category(&self) -> String {
         self.category.clone()
@@ -115,47 +502,517 @@ impl<B: BackOff> PositionStore for Consumer<B> {
     }
 }
 
-pub trait BackOff {
-    fn wait(&mut self, messages_processed: u64);
+pub trait Stopper {
+    fn stop(&mut self);
 }
 
-struct SimpleBackOff {
-    poll_interval_milliseconds: u64,
+#[derive(Debug, Clone)]
+pub struct ConsumerStopper {
+    should_continue: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    interrupt: Interrupt,
 }
 
-impl BackOff for SimpleBackOff {
-    fn wait(&mut self, messages_processed: u64) {
-        if messages_processed == 0 {
-            thread::sleep(Duration::from_millis(self.poll_interval_milliseconds));
+impl ConsumerStopper {
+    // Stops the consumer and waits for it to finish the message in hand and
+    // write its position, returning false if it's still running at the timeout
+    pub fn stop_and_wait(&mut self, timeout: Duration) -> bool {
+        self.stop();
+
+        let deadline = Instant::now() + timeout;
+        let interval = Duration::from_millis(STOP_WAIT_INTERVAL_MILLISECONDS);
+
+        while self.running.load(Ordering::SeqCst) {
+            if Instant::now() >= deadline {
+                return false;
+            }
+
+            thread::sleep(interval);
         }
+
+        true
     }
 }
 
-pub trait Stopper {
-    fn stop(&mut self) -> Result<(), Error>;
+impl Stopper for ConsumerStopper {
+    fn stop(&mut self) {
+        self.should_continue.store(false, Ordering::SeqCst);
+        self.interrupt.interrupt();
+    }
 }
 
-#[derive(Debug)]
-pub struct ConsumerStopper {
-    should_continue: Arc<Mutex<bool>>,
-}
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-impl Stopper for ConsumerStopper {
-    fn stop(&mut self) -> Result<(), Error> {
-        let mut lock = self.should_continue.lock().map_err(|mut poisoned_error| {
-            let should_continue = poisoned_error.get_mut();
+    use crate::consumer::{ErrorPolicy, Settings};
+    use crate::message_store::{controls, Condition, Get, MessageData, Put};
+    use crate::messaging::controls::message::{self, Event};
+    use crate::messaging::Metadata;
+    use crate::{clock, handlers, identity, stream_name, Error, FixedClock};
 
-            **should_continue = false;
+    use super::*;
 
-            Error::ConsumerError
-        })?;
+    fn consumer() -> Consumer<SimpleBackOff> {
+        let category = stream_name::controls::unique_category();
+        let settings = Settings {
+            identifier: Some(identity::random(8)),
+            poll_interval_milliseconds: Some(10),
+            ..Default::default()
+        };
 
-        *lock = false;
+        Consumer::new(category, controls::message_store(), settings)
+    }
 
-        Ok(())
+    fn write_event(consumer: &mut Consumer<SimpleBackOff>) -> MessageData {
+        let stream_name = stream_name!(&consumer.category, id = identity::uuid());
+
+        consumer
+            .store
+            .put(&controls::new_example(), &stream_name, None)
+            .unwrap()
+    }
+
+    fn failure() -> Error {
+        Error::MissingField("someField")
     }
-}
 
-pub trait Handler {
-    fn handle<T: TryFrom<MessageData>>(&mut self, message: T) -> Result<(), Error>;
+    #[test]
+    fn handles_messages_and_advances_the_position() {
+        static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+        let mut consumer = consumer();
+        consumer.add_handler::<Event>(|_| {
+            HANDLED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+
+        write_event(&mut consumer);
+        write_event(&mut consumer);
+
+        let processed = consumer.poll().unwrap();
+
+        assert_eq!(2, processed);
+        assert_eq!(2, HANDLED.load(Ordering::SeqCst));
+        assert!(consumer.current_position > STARTING_POSITION);
+        assert_eq!(0, consumer.poll().unwrap());
+    }
+
+    #[test]
+    fn consumes_the_whole_store() {
+        static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+        let categories = vec![
+            stream_name::controls::unique_category(),
+            stream_name::controls::unique_category(),
+        ];
+        let settings = Settings {
+            identifier: Some(identity::random(8)),
+            categories: Some(categories.clone()),
+            ..Default::default()
+        };
+        let mut consumer = Consumer::new(String::from(ALL), controls::message_store(), settings);
+        consumer.add_handler::<Event>(|_| {
+            HANDLED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+
+        for category in &categories {
+            let stream_name = stream_name!(category, id = identity::uuid());
+            let _: MessageData = consumer
+                .store
+                .put(&controls::new_example(), &stream_name, None)
+                .unwrap();
+        }
+
+        assert_eq!(2, consumer.poll().unwrap());
+        assert_eq!(2, HANDLED.load(Ordering::SeqCst));
+
+        consumer.update_position().unwrap();
+
+        assert_eq!(0, consumer.poll().unwrap());
+    }
+
+    #[test]
+    fn hands_messages_to_registered_handlers() {
+        static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+        struct Counter;
+
+        #[handlers]
+        impl Counter {
+            #[handler(Event)]
+            fn handle_event(
+                &mut self,
+                _: Message<Event>,
+                context: &mut Context,
+            ) -> Result<(), Error> {
+                assert!(context.identifier.is_some());
+                assert!(context.position.is_some());
+
+                HANDLED.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        let mut consumer = consumer();
+        consumer.register(Counter);
+
+        write_event(&mut consumer);
+
+        assert_eq!(1, consumer.poll().unwrap());
+        assert_eq!(1, HANDLED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn handlers_and_their_writes_use_the_consumers_clock() {
+        struct Recorder {
+            stream_name: String,
+        }
+
+        #[handlers]
+        impl Recorder {
+            #[handler(Event)]
+            fn handle_event(
+                &mut self,
+                event: Message<Event>,
+                context: &mut Context,
+            ) -> Result<(), Error> {
+                assert_eq!(clock::controls::time(), context.clock.now());
+
+                let recorded = Message::from_t(event.into_inner());
+                context.store.write(&recorded, &self.stream_name, None)?;
+
+                Ok(())
+            }
+        }
+
+        let stream_name = stream_name::controls::unique_example();
+        let mut consumer = consumer().with_clock(FixedClock::new(clock::controls::time()));
+        consumer.register(Recorder {
+            stream_name: stream_name.clone(),
+        });
+
+        write_event(&mut consumer);
+
+        assert_eq!(1, consumer.poll().unwrap());
+
+        let recorded = consumer.store.get_last(&stream_name).unwrap().unwrap();
+        let metadata: Metadata = serde_json::from_value(recorded.metadata).unwrap();
+
+        assert_eq!(Some(clock::controls::time()), metadata.time);
+    }
+
+    #[test]
+    fn stop_policy_returns_the_error() {
+        let mut consumer = consumer();
+        consumer.add_handler::<Event>(|_| Err(failure()));
+
+        write_event(&mut consumer);
+
+        assert!(consumer.poll().is_err());
+        assert_eq!(STARTING_POSITION, consumer.current_position);
+    }
+
+    #[test]
+    fn skip_policy_moves_on_to_the_next_message() {
+        let mut consumer = consumer();
+        consumer.add_handler_with_policy::<Event>(|_| Err(failure()), ErrorPolicy::Skip);
+
+        write_event(&mut consumer);
+        write_event(&mut consumer);
+
+        assert_eq!(2, consumer.poll().unwrap());
+    }
+
+    #[test]
+    fn retry_policy_handles_the_message_again() {
+        static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+        let mut consumer = consumer();
+        consumer.add_handler_with_policy::<Event>(
+            |_| {
+                if ATTEMPTS.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(failure())
+                } else {
+                    Ok(())
+                }
+            },
+            ErrorPolicy::retry(2, 1, ErrorPolicy::Stop),
+        );
+
+        write_event(&mut consumer);
+
+        assert_eq!(1, consumer.poll().unwrap());
+        assert_eq!(3, ATTEMPTS.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn retry_policy_falls_back_when_retries_are_exhausted() {
+        let mut consumer = consumer();
+        consumer.add_handler_with_policy::<Event>(
+            |_| Err(failure()),
+            ErrorPolicy::retry(1, 1, ErrorPolicy::Stop),
+        );
+
+        write_event(&mut consumer);
+
+        assert!(consumer.poll().is_err());
+    }
+
+    #[test]
+    fn stopping_gives_up_retrying() {
+        let mut consumer = consumer();
+        consumer.add_handler_with_policy::<Event>(
+            |_| Err(failure()),
+            ErrorPolicy::retry(5, 60_000, ErrorPolicy::Skip),
+        );
+
+        write_event(&mut consumer);
+
+        let mut stopper = consumer.stopper();
+        let running = thread::spawn(move || {
+            let result = consumer.start();
+            (consumer, result)
+        });
+
+        thread::sleep(Duration::from_millis(200));
+
+        assert!(stopper.stop_and_wait(Duration::from_secs(5)));
+
+        let (mut consumer, result) = running.join().unwrap();
+        let identifier = consumer.settings.identifier.clone();
+
+        assert!(result.is_err());
+        assert_eq!(None, consumer.get_last(identifier.as_deref()).unwrap());
+    }
+
+    #[test]
+    fn dead_letter_policy_writes_to_the_dead_letter_stream() {
+        let mut consumer = consumer();
+        consumer.add_handler_with_policy::<Event>(|_| Err(failure()), ErrorPolicy::DeadLetter);
+
+        let written = write_event(&mut consumer);
+
+        assert_eq!(1, consumer.poll().unwrap());
+
+        let stream_name = consumer.dead_letter_stream_name().unwrap();
+        let dead_letter = consumer.store.get_last(&stream_name).unwrap().unwrap();
+        let dead_letter: Message<DeadLetter> = Message::try_from(dead_letter).unwrap();
+
+        assert_eq!(written.id, dead_letter.message.id);
+        assert_eq!(failure().to_string(), dead_letter.error);
+    }
+
+    #[test]
+    fn replays_dead_letters_to_their_handler() {
+        static REPLAYED: AtomicUsize = AtomicUsize::new(0);
+
+        let mut consumer = consumer();
+        consumer.add_handler_with_policy::<Event>(|_| Err(failure()), ErrorPolicy::DeadLetter);
+
+        write_event(&mut consumer);
+        write_event(&mut consumer);
+        consumer.poll().unwrap();
+
+        consumer.add_handler::<Event>(|_| {
+            REPLAYED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+
+        let replayed = consumer.replay_dead_letters().unwrap();
+
+        assert_eq!(2, replayed);
+        assert_eq!(2, REPLAYED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn replays_only_dead_letters_written_since_the_last_replay() {
+        static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+        let mut consumer = consumer();
+        consumer.add_handler_with_policy::<Event>(
+            |_| {
+                ATTEMPTS.fetch_add(1, Ordering::SeqCst);
+                Err(failure())
+            },
+            ErrorPolicy::DeadLetter,
+        );
+
+        write_event(&mut consumer);
+        write_event(&mut consumer);
+        consumer.poll().unwrap();
+
+        // Each replay fails again, dead lettering the messages once more
+        assert_eq!(2, consumer.replay_dead_letters().unwrap());
+        assert_eq!(2, consumer.replay_dead_letters().unwrap());
+        assert_eq!(6, ATTEMPTS.load(Ordering::SeqCst));
+
+        consumer.add_handler::<Event>(|_| Ok(()));
+
+        assert_eq!(2, consumer.replay_dead_letters().unwrap());
+        assert_eq!(0, consumer.replay_dead_letters().unwrap());
+    }
+
+    #[test]
+    fn replays_only_its_own_dead_letters() {
+        let mut identified = consumer();
+        identified.add_handler_with_policy::<Event>(|_| Err(failure()), ErrorPolicy::DeadLetter);
+
+        write_event(&mut identified);
+        identified.poll().unwrap();
+
+        let mut unidentified = Consumer::new(
+            identified.category.clone(),
+            controls::message_store(),
+            Settings::default(),
+        );
+        unidentified.add_handler::<Event>(|_| Ok(()));
+
+        assert_eq!(0, unidentified.replay_dead_letters().unwrap());
+    }
+
+    #[test]
+    fn handles_only_messages_correlated_to_the_correlation_category() {
+        static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+        let correlation = stream_name::controls::unique_category();
+
+        let mut consumer = consumer();
+        consumer.settings = consumer.settings.clone().correlated(&correlation).unwrap();
+        consumer.add_handler::<Event>(|_| {
+            HANDLED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+
+        write_event(&mut consumer);
+
+        let mut correlated = message::new::event();
+        correlated.correlate(&stream_name!(&correlation, id = identity::uuid()));
+        let stream_name = stream_name!(&consumer.category, id = identity::uuid());
+        consumer
+            .store
+            .write(&correlated, &stream_name, None)
+            .unwrap();
+
+        assert_eq!(1, consumer.poll().unwrap());
+        assert_eq!(1, HANDLED.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn writes_its_position_when_stopped() {
+        static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+        let mut consumer = consumer();
+        consumer.add_handler::<Event>(|_| {
+            HANDLED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+
+        write_event(&mut consumer);
+        write_event(&mut consumer);
+
+        let identifier = consumer.settings.identifier.clone();
+        let mut stopper = consumer.stopper();
+
+        let running = thread::spawn(move || {
+            let summary = consumer.start();
+            (consumer, summary)
+        });
+
+        while HANDLED.load(Ordering::SeqCst) < 2 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(stopper.stop_and_wait(Duration::from_secs(5)));
+
+        let (mut consumer, summary) = running.join().unwrap();
+        let summary = summary.unwrap();
+
+        assert_eq!(2, summary.messages_processed);
+        assert_eq!(
+            Some(summary.position),
+            consumer.get_last(identifier.as_deref()).unwrap()
+        );
+    }
+
+    #[test]
+    fn stop_and_wait_does_not_wait_out_the_poll_interval() {
+        let settings = Settings {
+            poll_interval_milliseconds: Some(60_000),
+            ..Default::default()
+        };
+        let mut consumer = Consumer::new(
+            stream_name::controls::unique_category(),
+            controls::message_store(),
+            settings,
+        );
+
+        let mut stopper = consumer.stopper();
+        let running = thread::spawn(move || consumer.start());
+
+        thread::sleep(Duration::from_millis(200));
+
+        assert!(stopper.stop_and_wait(Duration::from_secs(5)));
+
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn fails_to_start_when_the_server_does_not_allow_conditions() {
+        let mut consumer = consumer();
+        consumer.settings = consumer
+            .settings
+            .clone()
+            .with_condition(&Condition::new().message_type("Event"));
+        consumer
+            .store
+            .client
+            .batch_execute("SET message_store.sql_condition = off")
+            .unwrap();
+
+        let result = consumer.start();
+
+        assert!(matches!(result, Err(Error::SqlConditionDisabled)));
+    }
+
+    #[test]
+    fn starts_with_a_condition_when_the_server_allows_them() {
+        let mut consumer = consumer();
+        consumer.settings = consumer
+            .settings
+            .clone()
+            .with_condition(&Condition::new().message_type("Event"));
+        consumer
+            .store
+            .client
+            .batch_execute("SET message_store.sql_condition = on")
+            .unwrap();
+
+        consumer.stopper().stop();
+
+        assert!(consumer.start().is_ok());
+    }
+
+    #[test]
+    fn does_not_poll_when_stopped_before_starting() {
+        let mut consumer = consumer();
+        consumer.add_handler::<Event>(|_| Ok(()));
+
+        write_event(&mut consumer);
+
+        consumer.stopper().stop();
+
+        let summary = consumer.start().unwrap();
+
+        assert_eq!(0, summary.messages_processed);
+        assert_eq!(STARTING_POSITION, summary.position);
+    }
+
+    #[test]
+    fn stop_and_wait_returns_immediately_when_not_running() {
+        let consumer = consumer();
+
+        assert!(consumer.stopper().stop_and_wait(Duration::from_millis(0)));
+    }
 }
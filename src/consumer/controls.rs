@@ -1 +1,53 @@
+#[cfg(test)]
+pub(crate) use self::writer::{writer, Writer};
 
+#[cfg(test)]
+mod writer {
+    use crate::consumer::entity_cache::DontCache;
+    use crate::consumer::entity_store::{EntityStore, EntityStoreEntity};
+    use crate::consumer::write_message::WriteMessage;
+    use crate::message_store::{controls, MessageStore};
+    use crate::stream_name;
+
+    // Writes messages and fetches entities of its category, for tests of
+    // the consumer's writing helpers
+    pub(crate) struct Writer {
+        pub(crate) category: String,
+        pub(crate) store: MessageStore,
+        pub(crate) cache: DontCache,
+    }
+
+    impl<T: EntityStoreEntity> EntityStore<T> for Writer {
+        type Cache = DontCache;
+
+        fn get_category(&self) -> String {
+            self.category.clone()
+        }
+
+        fn get_store(&mut self) -> &mut MessageStore {
+            &mut self.store
+        }
+
+        fn get_cache(&mut self) -> &mut Self::Cache {
+            &mut self.cache
+        }
+    }
+
+    impl WriteMessage for Writer {
+        fn get_category(&self) -> String {
+            self.category.clone()
+        }
+
+        fn get_store(&mut self) -> &mut MessageStore {
+            &mut self.store
+        }
+    }
+
+    pub(crate) fn writer() -> Writer {
+        Writer {
+            category: stream_name::controls::unique_category(),
+            store: controls::message_store(),
+            cache: DontCache,
+        }
+    }
+}
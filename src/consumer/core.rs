@@ -1,4 +1,8 @@
-use crate::message_store::{Get, MessageData, MessageStore, Put};
+use crate::message_store;
+use crate::message_store::Condition;
+use crate::stream_name;
+use crate::stream_name::utils::{get_category_types, get_entity_name, is_category};
+use crate::Error;
 
 #[derive(Default, Clone)]
 pub struct Settings {
@@ -11,4 +15,113 @@ pub struct Settings {
     pub poll_interval_milliseconds: Option<u64>, // poll_interval_milliseconds
     pub position_update_interval: Option<i64>,   // position_update_interval
     pub identifier: Option<String>,              // identifier
+    pub categories: Option<Vec<String>>,         // categories, when consuming ALL
+}
+
+impl Settings {
+    // Only reads messages correlated to streams in `category`, as when a
+    // process manager waits on replies to the commands it has sent
+    pub fn correlated(self, category: &str) -> Result<Self, Error> {
+        if !is_category(category) {
+            return Err(not_a_category(category));
+        }
+
+        Ok(Self {
+            correlation: Some(String::from(category)),
+            ..self
+        })
+    }
+
+    pub fn with_condition(self, condition: &Condition) -> Self {
+        Self {
+            condition: condition.to_sql(),
+            ..self
+        }
+    }
+}
+
+impl From<&Settings> for message_store::Settings {
+    fn from(settings: &Settings) -> message_store::Settings {
+        message_store::Settings {
+            batch_size: settings.batch_size,
+            correlation: settings.correlation.clone(),
+            group_member: settings.group_member,
+            group_size: settings.group_size,
+            condition: settings.condition.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+pub(crate) fn not_a_category(stream_name: &str) -> Error {
+    Error::StreamName {
+        stream_name: String::from(stream_name),
+        reason: String::from("expected a category"),
+    }
+}
+
+// Streams a consumer keeps for itself alongside a category, such as
+// `account:position-worker1`
+pub(crate) fn consumer_stream_name(
+    stream_name: &str,
+    category_type: &str,
+    consumer_identifier: Option<&str>,
+) -> Option<String> {
+    if is_category(stream_name) {
+        let category_type = category_type.to_string();
+        let category_types = if let Some(mut types) = get_category_types(stream_name) {
+            if types.contains(&category_type) {
+                types
+            } else {
+                types.push(category_type);
+                types
+            }
+        } else {
+            vec![category_type]
+        };
+
+        let entity_name = get_entity_name(stream_name);
+
+        let consumer_stream_name = if let Some(consumer) = consumer_identifier {
+            stream_name!(&entity_name, category_types = category_types, id = consumer)
+        } else {
+            stream_name!(&entity_name, category_types = category_types)
+        };
+        Some(consumer_stream_name)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message_store::Condition;
+    use crate::{stream_name, Error};
+
+    use super::Settings;
+
+    #[test]
+    fn correlates_to_a_category() {
+        let settings = Settings::default().correlated("transfer").unwrap();
+
+        assert_eq!(Some(String::from("transfer")), settings.correlation);
+    }
+
+    #[test]
+    fn filters_with_a_condition() {
+        let condition = Condition::new().message_type("Deposited");
+
+        let settings = Settings::default().with_condition(&condition);
+
+        assert_eq!(condition.to_sql(), settings.condition);
+    }
+
+    #[test]
+    fn does_not_correlate_to_a_stream() {
+        let stream_name = stream_name::controls::unique_example();
+
+        let result = Settings::default().correlated(&stream_name);
+
+        assert!(matches!(result, Err(Error::StreamName { .. })));
+    }
 }
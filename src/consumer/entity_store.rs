@@ -1,13 +1,10 @@
 use crate::consumer::entity_cache::EntityCache;
-use crate::message_store::{Get, MessageData, MessageStore};
-use crate::{messaging::Message, stream_name, Error};
+use crate::message_store::{MessageData, MessageStore, Read};
+use crate::{messaging::Message, metrics, stream_name, Error};
 use serde::{de::DeserializeOwned, Serialize};
 
 use std::convert::TryFrom;
 
-// TODO: make sure it matches messagedbs default
-const BATCH_SIZE_DEFAULT: i64 = 1000;
-
 pub trait EntityStoreEntity: Serialize + DeserializeOwned + Default + Clone {
     type Projector: EntityBuilder<Self>;
     fn get_projector() -> Self::Projector;
@@ -20,45 +17,43 @@ pub trait EntityStore<T: EntityStoreEntity> {
     fn get_cache(&mut self) -> &mut Self::Cache;
 
     fn fetch(&mut self, identity: &str) -> Result<T, Error> {
+        self.fetch_with_version(identity).map(|(entity, _)| entity)
+    }
+
+    // The version is the position of the last message applied, -1 when the
+    // stream is empty, so it can be used directly as an expected version
+    fn fetch_with_version(&mut self, identity: &str) -> Result<(T, i64), Error> {
         let category = &self.get_category();
+
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::debug_span!("evt.fetch", category = category.as_str(), identity).entered();
+
         let entity_info: Option<(i64, T)> = self.get_cache().get_from_cache(category, identity);
+        metrics::entity_cache(category, entity_info.is_some());
         let mut position = -1;
         let mut entity_builder = T::get_projector();
         if let Some((cached_position, cached_entity)) = entity_info {
             position = cached_position;
             entity_builder.initialize(cached_entity);
         }
-        loop {
-            let messages = self
-                .get_store()
-                .get(&stream_name!(category, id = identity), Some(position))?;
-            let messages_length = messages.len();
+        let stream_name = stream_name!(category, id = identity);
 
-            // handle all the messages
-            for message_data in messages {
-                // Position should be set as were reading
-                if let Some(message_position) = message_data.position {
-                    position = message_position;
-                }
-                entity_builder.apply(message_data);
-            }
+        for message_data in self.get_store().read_from(&stream_name, position + 1) {
+            let message_data = message_data?;
 
-            if messages_length
-                < self
-                    .get_store()
-                    .settings
-                    .batch_size
-                    .unwrap_or(BATCH_SIZE_DEFAULT) as usize
-            {
-                break;
+            // Position should be set as were reading
+            if let Some(message_position) = message_data.position {
+                position = message_position;
             }
+            entity_builder.apply(message_data);
         }
 
         let entity = entity_builder.entity();
 
         self.get_cache()
             .set_in_cache(category, identity, position, entity.clone());
-        Ok(entity)
+        Ok((entity, position))
     }
     // fn get<T: EntityStoreEntity>(&mut self, identity: &str) -> Result<Option<T>, Error>;
 }
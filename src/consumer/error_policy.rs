@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+use crate::consumer::core::consumer_stream_name;
+use crate::message_store::MessageData;
+use crate::messaging::MessageType;
+
+const DEAD_LETTER_TYPE: &str = "deadletter";
+// Consumers without an identifier still need an id segment, otherwise their
+// dead letter stream name is a category that takes in every other consumer's
+const DEFAULT_IDENTIFIER: &str = "default";
+
+#[derive(Debug, Clone, Default)]
+pub enum ErrorPolicy {
+    // Stops the consumer, returning the error from `start`
+    #[default]
+    Stop,
+    // Logs the error and moves on to the next message
+    Skip,
+    // Writes the message and the error to the consumer's dead letter stream
+    // and moves on to the next message
+    DeadLetter,
+    // Handles the message again, doubling the interval between attempts, and
+    // falls back to `then` when every retry has failed. Stopping the consumer
+    // gives up, failing with the last error and leaving the message unhandled.
+    Retry {
+        retries: u32,
+        interval_milliseconds: u64,
+        then: Box<ErrorPolicy>,
+    },
+}
+
+impl ErrorPolicy {
+    pub fn retry(retries: u32, interval_milliseconds: u64, then: ErrorPolicy) -> Self {
+        ErrorPolicy::Retry {
+            retries,
+            interval_milliseconds,
+            then: Box::new(then),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub message: MessageData,
+    pub error: String,
+}
+
+impl MessageType for DeadLetter {
+    fn message_type() -> String {
+        String::from("DeadLetter")
+    }
+}
+
+pub fn dead_letter_stream_name(
+    category: &str,
+    consumer_identifier: Option<&str>,
+) -> Option<String> {
+    consumer_stream_name(
+        category,
+        DEAD_LETTER_TYPE,
+        Some(dead_letter_identifier(consumer_identifier)),
+    )
+}
+
+// The category of every consumer's dead letter stream, which is also where
+// replays keep their positions
+pub(crate) fn dead_letter_category(category: &str) -> Option<String> {
+    consumer_stream_name(category, DEAD_LETTER_TYPE, None)
+}
+
+pub(crate) fn dead_letter_identifier(consumer_identifier: Option<&str>) -> &str {
+    consumer_identifier.unwrap_or(DEFAULT_IDENTIFIER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dead_letter_stream_is_a_category_type() {
+        let name = dead_letter_stream_name("account:command", None).unwrap();
+
+        assert_eq!("account:command+deadletter-default", name);
+    }
+
+    #[test]
+    fn dead_letter_stream_includes_the_consumer_identifier() {
+        let name = dead_letter_stream_name("account", Some("worker")).unwrap();
+
+        assert_eq!("account:deadletter-worker", name);
+    }
+}
@@ -0,0 +1,151 @@
+use std::marker::PhantomData;
+
+use crate::message_store::MessageData;
+use crate::messaging::MessageType;
+use crate::{Clock, Error, MessageStore};
+
+pub type HandlerFn = fn(message_data: MessageData) -> Result<(), Error>;
+
+// What a handler knows about the consumer handing it a message
+pub struct Context<'a> {
+    pub store: &'a mut MessageStore,
+    pub clock: &'a dyn Clock,
+    pub category: &'a str,
+    pub identifier: Option<&'a str>,
+    pub position: Option<i64>, // global position of the message being handled
+}
+
+// Handles any of the message types it lists. Implement it with `#[handlers]`
+// to receive typed messages:
+//
+//     #[handlers]
+//     impl AccountHandler {
+//         #[handler(Deposit)]
+//         fn handle_deposit(&mut self, deposit: Message<Deposit>, context: &mut Context) -> Result<(), Error> {
+//             ...
+//         }
+//     }
+pub trait Handler {
+    fn message_types(&self) -> Vec<String>;
+    fn handle(&mut self, message_data: MessageData, context: &mut Context<'_>)
+        -> Result<(), Error>;
+}
+
+pub(crate) struct FnHandler<T> {
+    handler: HandlerFn,
+    message_type: PhantomData<fn() -> T>,
+}
+
+impl<T> FnHandler<T> {
+    pub(crate) fn new(handler: HandlerFn) -> Self {
+        Self {
+            handler,
+            message_type: PhantomData,
+        }
+    }
+}
+
+impl<T: MessageType> Handler for FnHandler<T> {
+    fn message_types(&self) -> Vec<String> {
+        vec![T::message_type()]
+    }
+
+    fn handle(&mut self, message_data: MessageData, _: &mut Context<'_>) -> Result<(), Error> {
+        (self.handler)(message_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use crate::message_store::{controls, MessageData};
+    use crate::messaging::controls::message::{Command, Event};
+    use crate::messaging::{Message, MessageType};
+    use crate::{handlers, Clock, Error, SystemClock};
+
+    use super::{Context, Handler};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<Event>,
+        positions: Vec<Option<i64>>,
+        commands: usize,
+    }
+
+    #[handlers]
+    impl Recorder {
+        #[handler(Event)]
+        fn handle_event(
+            &mut self,
+            event: Message<Event>,
+            context: &mut Context,
+        ) -> Result<(), Error> {
+            self.positions.push(context.position);
+            self.events.push(event.into_inner());
+            Ok(())
+        }
+
+        #[handler(Command)]
+        fn handle_command(&mut self, _: Message<Command>) -> Result<(), Error> {
+            self.commands += 1;
+            Ok(())
+        }
+
+        fn unmarked(&self) -> usize {
+            self.events.len()
+        }
+    }
+
+    fn handle(handler: &mut dyn Handler, message_data: MessageData) -> Result<(), Error> {
+        let mut store = controls::message_store();
+        let clock = SystemClock;
+        let mut context = Context {
+            store: &mut store,
+            clock: &clock as &dyn Clock,
+            category: "test",
+            identifier: None,
+            position: message_data.global_position,
+        };
+
+        handler.handle(message_data, &mut context)
+    }
+
+    #[test]
+    fn lists_the_handled_message_types() {
+        let handler = Recorder::default();
+
+        assert_eq!(
+            vec![Event::message_type(), Command::message_type()],
+            handler.message_types()
+        );
+    }
+
+    #[test]
+    fn hands_typed_messages_to_their_methods() {
+        let mut handler = Recorder::default();
+        let mut event = controls::example();
+        event.global_position = Some(11);
+
+        handle(&mut handler, event.clone()).unwrap();
+
+        let expected = Message::<Event>::try_from(event).unwrap().into_inner();
+
+        assert_eq!(1, handler.unmarked());
+        assert_eq!(expected.field1, handler.events[0].field1);
+        assert_eq!(vec![Some(11)], handler.positions);
+        assert_eq!(0, handler.commands);
+    }
+
+    #[test]
+    fn ignores_unhandled_message_types() {
+        let mut handler = Recorder::default();
+        let mut message = controls::example();
+        message.message_type = String::from("Unhandled");
+
+        handle(&mut handler, message).unwrap();
+
+        assert_eq!(0, handler.unmarked());
+        assert_eq!(0, handler.commands);
+    }
+}
@@ -0,0 +1,173 @@
+use postgres::fallible_iterator::FallibleIterator;
+use postgres::Client;
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+use crate::consumer::back_off::{BackOff, Interrupt};
+use crate::message_store::ALL;
+use crate::{db, Error};
+
+// Channel the notify_message trigger publishes the category of each new
+// message on, see message-db/docker-entrypoint-initdb.d/install-notify-trigger.sh
+pub const CHANNEL: &str = "messages";
+
+// Notifications can't be waited on alongside the interrupt, so the wait checks
+// it this often instead
+const INTERRUPT_CHECK_INTERVAL_MILLISECONDS: u64 = 100;
+
+pub struct NotifyBackOff {
+    client: Client,
+    category: String,
+    timeout: Duration,
+    interrupt: Interrupt,
+}
+
+impl NotifyBackOff {
+    pub fn build(category: &str, poll_interval_milliseconds: u64) -> Result<Self, Error> {
+        Self::new(db::build(), category, poll_interval_milliseconds)
+    }
+
+    pub fn new(
+        mut client: Client,
+        category: &str,
+        poll_interval_milliseconds: u64,
+    ) -> Result<Self, Error> {
+        client.batch_execute(&format!("LISTEN {}", CHANNEL))?;
+
+        Ok(Self {
+            client,
+            category: String::from(category),
+            timeout: Duration::from_millis(poll_interval_milliseconds),
+            interrupt: Interrupt::default(),
+        })
+    }
+}
+
+impl BackOff for NotifyBackOff {
+    fn wait(&mut self, messages_processed: u64) {
+        let mut notifications = self.client.notifications();
+
+        // The next poll will pick up anything these were announcing
+        if messages_processed > 0 {
+            while let Ok(Some(_)) = notifications.iter().next() {}
+            return;
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        let interval = Duration::from_millis(INTERRUPT_CHECK_INTERVAL_MILLISECONDS);
+
+        while !self.interrupt.is_interrupted() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining == Duration::from_millis(0) {
+                return;
+            }
+
+            match notifications
+                .timeout_iter(cmp::min(remaining, interval))
+                .next()
+            {
+                Ok(Some(notification)) if notified(&self.category, notification.payload()) => {
+                    return
+                }
+                Ok(_) => continue,
+                Err(_) => {
+                    // Without a listening connection this degrades to polling
+                    self.interrupt.sleep(remaining);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn interrupt_with(&mut self, interrupt: Interrupt) {
+        self.interrupt = interrupt;
+    }
+}
+
+// A consumer of the whole store is interested in every category
+fn notified(category: &str, payload: &str) -> bool {
+    category == ALL || payload == category
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::consumer::back_off::{BackOff, Interrupt};
+    use crate::{db, stream_name};
+
+    use super::{NotifyBackOff, CHANNEL};
+
+    fn notify(category: &str) {
+        db::build()
+            .execute("SELECT pg_notify($1, $2)", &[&CHANNEL, &category])
+            .unwrap();
+    }
+
+    #[test]
+    fn wakes_when_the_category_is_notified() {
+        let category = stream_name::controls::unique_category();
+        let mut back_off = NotifyBackOff::build(&category, 10_000).unwrap();
+
+        let notified = category.clone();
+        let notifier = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            notify(&notified);
+        });
+
+        let start = Instant::now();
+        back_off.wait(0);
+
+        notifier.join().unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn ignores_notifications_for_other_categories() {
+        let category = stream_name::controls::unique_category();
+        let mut back_off = NotifyBackOff::build(&category, 300).unwrap();
+
+        notify(&stream_name::controls::unique_category());
+
+        let start = Instant::now();
+        back_off.wait(0);
+
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn stops_waiting_when_interrupted() {
+        let category = stream_name::controls::unique_category();
+        let mut back_off = NotifyBackOff::build(&category, 10_000).unwrap();
+
+        let interrupt = Interrupt::default();
+        back_off.interrupt_with(interrupt.clone());
+
+        let interrupting = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            interrupt.interrupt();
+        });
+
+        let start = Instant::now();
+        back_off.wait(0);
+
+        interrupting.join().unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn does_not_wait_when_messages_were_processed() {
+        let category = stream_name::controls::unique_category();
+        let mut back_off = NotifyBackOff::build(&category, 10_000).unwrap();
+
+        let start = Instant::now();
+        back_off.wait(1);
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,14 +1,14 @@
 use serde::{Deserialize, Serialize};
 
-use crate::message_store::{Get, MessageStore};
+use crate::consumer::core::{consumer_stream_name, not_a_category};
+use crate::message_store::{get, Get, MessageData, MessageStore};
 use crate::messaging::{Message, MessageType, Write};
-use crate::stream_name;
-use crate::stream_name::utils::{get_category_types, is_category};
-use crate::Error;
+use crate::{DateTime, Error, Utc};
 
 use std::convert::TryFrom;
 
 const POSITION_TYPE: &'static str = "position";
+const STARTING_POSITION: i64 = 0;
 
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 struct Position {
@@ -21,61 +21,349 @@ impl MessageType for Position {
     }
 }
 
+// Where a consumer is in its category, and how far it has to go
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerStatus {
+    pub category: String,
+    pub identifier: Option<String>,
+    pub position_stream_name: String,
+    pub position: Option<i64>,
+    pub last_global_position: Option<i64>,
+    pub lag: i64,
+    pub updated: Option<DateTime<Utc>>,
+}
+
+// Where a reset moves a consumer's position to. Consumers read from the message
+// after their position, so each target is the position just before the first
+// message to process again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetTo {
+    Start,
+    GlobalPosition(i64),
+    Time(DateTime<Utc>),
+}
+
+// What a reset did, or would do when it's a dry run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reset {
+    pub position_stream_name: String,
+    pub previous_position: Option<i64>,
+    pub position: i64,
+    pub messages_to_process: i64,
+    pub dry_run: bool,
+}
+
 pub trait PositionStore {
     fn get_category(&self) -> String;
     fn get_store(&mut self) -> &mut MessageStore;
 
     fn get_last(&mut self, consumer_identity: Option<&str>) -> Result<Option<i64>, Error> {
-        let position_stream_name =
-            Self::position_stream_name(&self.get_category(), consumer_identity)
-                .ok_or(Error::StreamName)?;
+        let category = self.get_category();
+        let position_stream_name = Self::position_stream_name(&category, consumer_identity)
+            .ok_or_else(|| not_a_category(&category))?;
 
         let last_message_data = self.get_store().get_last(&position_stream_name)?;
 
-        Ok(last_message_data.and_then(|message_data| {
-            Message::try_from(message_data)
-                .ok()
-                .map(|position: Message<Position>| position.into_inner().position)
-        }))
+        Ok(last_message_data.and_then(decode_position))
+    }
+
+    fn status(&mut self, consumer_identity: Option<&str>) -> Result<ConsumerStatus, Error> {
+        let category = self.get_category();
+        let position_stream_name = Self::position_stream_name(&category, consumer_identity)
+            .ok_or_else(|| not_a_category(&category))?;
+
+        let last_update = self.get_store().get_last(&position_stream_name)?;
+        let updated = last_update
+            .as_ref()
+            .and_then(|message_data| message_data.time);
+        let position = last_update.and_then(decode_position);
+
+        let last_global_position =
+            get::last_global_position(&mut self.get_store().client, &category)?;
+
+        let lag = (last_global_position.unwrap_or(0) - position.unwrap_or(0)).max(0);
+
+        Ok(ConsumerStatus {
+            category,
+            identifier: consumer_identity.map(String::from),
+            position_stream_name,
+            position,
+            last_global_position,
+            lag,
+            updated,
+        })
     }
 
     fn update(&mut self, consumer_identity: Option<&str>, position: i64) -> Result<(), Error> {
-        let position_stream_name =
-            Self::position_stream_name(&self.get_category(), consumer_identity)
-                .ok_or(Error::StreamName)?;
+        let category = self.get_category();
+        let position_stream_name = Self::position_stream_name(&category, consumer_identity)
+            .ok_or_else(|| not_a_category(&category))?;
 
         let position = Position { position };
         let message = Message::from_t(position);
 
         self.get_store()
-            .write(&message, &position_stream_name, None)
+            .write(&message, &position_stream_name, None)?;
+
+        Ok(())
+    }
+
+    // Moves the position so the consumer processes the category again from the
+    // target the next time it starts. Consumers that are running keep their
+    // own position in memory and overwrite it, so stop them first.
+    fn reset(
+        &mut self,
+        consumer_identity: Option<&str>,
+        to: ResetTo,
+        dry_run: bool,
+    ) -> Result<Reset, Error> {
+        let category = self.get_category();
+        let position_stream_name = Self::position_stream_name(&category, consumer_identity)
+            .ok_or_else(|| not_a_category(&category))?;
+
+        let previous_position = self.get_last(consumer_identity)?;
+
+        let client = &mut self.get_store().client;
+        let position = match to {
+            ResetTo::Start => STARTING_POSITION,
+            ResetTo::GlobalPosition(global_position) => global_position - 1,
+            ResetTo::Time(time) => match get::first_global_position_since(client, &category, time)?
+            {
+                Some(global_position) => global_position - 1,
+                None => get::last_global_position(client, &category)?.unwrap_or(STARTING_POSITION),
+            },
+        }
+        .max(STARTING_POSITION);
+
+        let messages_to_process = get::count_since(client, &category, position)?;
+
+        if !dry_run {
+            self.update(consumer_identity, position)?;
+        }
+
+        Ok(Reset {
+            position_stream_name,
+            previous_position,
+            position,
+            messages_to_process,
+            dry_run,
+        })
     }
 
     fn position_stream_name(
         stream_name: &str,
         consumer_identifier: Option<&str>,
     ) -> Option<String> {
-        if is_category(stream_name) {
-            let postition_type = POSITION_TYPE.to_string();
-            let category_types = if let Some(mut types) = get_category_types(stream_name) {
-                if types.contains(&postition_type) {
-                    types
-                } else {
-                    types.push(postition_type);
-                    types
-                }
-            } else {
-                vec![postition_type]
-            };
-
-            let position_stream_name = if let Some(consumer) = consumer_identifier {
-                stream_name!(stream_name, category_types = category_types, id = consumer)
-            } else {
-                stream_name!(stream_name, category_types = category_types)
-            };
-            Some(position_stream_name)
-        } else {
-            None
+        consumer_stream_name(stream_name, POSITION_TYPE, consumer_identifier)
+    }
+}
+
+fn decode_position(message_data: MessageData) -> Option<i64> {
+    Message::try_from(message_data)
+        .ok()
+        .map(|position: Message<Position>| position.into_inner().position)
+}
+
+pub(crate) struct Positions<'a> {
+    category: String,
+    store: &'a mut MessageStore,
+}
+
+impl<'a> Positions<'a> {
+    pub(crate) fn new(category: &str, store: &'a mut MessageStore) -> Self {
+        Self {
+            category: String::from(category),
+            store,
         }
     }
 }
+
+impl<'a> PositionStore for Positions<'a> {
+    fn get_category(&self) -> String {
+        self.category.clone()
+    }
+
+    fn get_store(&mut self) -> &mut MessageStore {
+        self.store
+    }
+}
+
+// The status of any consumer of the category, such as one in another process
+pub fn consumer_status(
+    store: &mut MessageStore,
+    category: &str,
+    consumer_identity: Option<&str>,
+) -> Result<ConsumerStatus, Error> {
+    let mut positions = Positions {
+        category: String::from(category),
+        store,
+    };
+
+    positions.status(consumer_identity)
+}
+
+// Resets the position of any consumer of the category, such as one in another
+// process, so it replays messages after a fix
+pub fn reset_position(
+    store: &mut MessageStore,
+    category: &str,
+    consumer_identity: Option<&str>,
+    to: ResetTo,
+    dry_run: bool,
+) -> Result<Reset, Error> {
+    let mut positions = Positions {
+        category: String::from(category),
+        store,
+    };
+
+    positions.reset(consumer_identity, to, dry_run)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message_store::{controls, MessageData, Put};
+    use crate::{identity, stream_name};
+
+    use super::*;
+
+    fn write_messages(store: &mut MessageStore, category: &str, count: usize) -> Vec<MessageData> {
+        (0..count)
+            .map(|_| {
+                let stream_name = stream_name!(category, id = identity::uuid());
+                store
+                    .put(&controls::new_example(), &stream_name, None)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_the_lag_of_a_consumer_that_has_not_started() {
+        let mut store = controls::message_store();
+        let category = stream_name::controls::unique_category();
+
+        write_messages(&mut store, &category, 2);
+
+        let status = consumer_status(&mut store, &category, Some("worker")).unwrap();
+        let last_global_position = status.last_global_position.unwrap();
+
+        assert_eq!(None, status.position);
+        assert_eq!(None, status.updated);
+        assert_eq!(last_global_position, status.lag);
+        assert_eq!(
+            format!("{}:position-worker", category),
+            status.position_stream_name
+        );
+    }
+
+    #[test]
+    fn reports_the_lag_behind_the_stored_position() {
+        let mut store = controls::message_store();
+        let category = stream_name::controls::unique_category();
+
+        write_messages(&mut store, &category, 3);
+
+        let status = consumer_status(&mut store, &category, None).unwrap();
+        let last_global_position = status.last_global_position.unwrap();
+
+        let mut positions = Positions {
+            category: category.clone(),
+            store: &mut store,
+        };
+        positions.update(None, last_global_position - 1).unwrap();
+
+        let status = positions.status(None).unwrap();
+
+        assert_eq!(Some(last_global_position - 1), status.position);
+        assert_eq!(1, status.lag);
+        assert!(status.updated.is_some());
+    }
+
+    #[test]
+    fn resets_to_the_start() {
+        let mut store = controls::message_store();
+        let category = stream_name::controls::unique_category();
+
+        write_messages(&mut store, &category, 3);
+
+        let status = consumer_status(&mut store, &category, None).unwrap();
+        let last_global_position = status.last_global_position.unwrap();
+
+        let mut positions = Positions {
+            category: category.clone(),
+            store: &mut store,
+        };
+        positions.update(None, last_global_position).unwrap();
+
+        let reset = positions.reset(None, ResetTo::Start, false).unwrap();
+
+        assert_eq!(Some(last_global_position), reset.previous_position);
+        assert_eq!(STARTING_POSITION, reset.position);
+        assert_eq!(3, reset.messages_to_process);
+        assert_eq!(Some(STARTING_POSITION), positions.get_last(None).unwrap());
+    }
+
+    #[test]
+    fn resets_to_resume_from_a_global_position() {
+        let mut store = controls::message_store();
+        let category = stream_name::controls::unique_category();
+
+        write_messages(&mut store, &category, 3);
+        let messages = store.get(&category, None).unwrap();
+        let second = messages[1].global_position.unwrap();
+
+        let reset = reset_position(
+            &mut store,
+            &category,
+            Some("worker"),
+            ResetTo::GlobalPosition(second),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(None, reset.previous_position);
+        assert_eq!(second - 1, reset.position);
+        assert_eq!(2, reset.messages_to_process);
+    }
+
+    #[test]
+    fn resets_to_the_first_message_at_or_after_a_time() {
+        let mut store = controls::message_store();
+        let category = stream_name::controls::unique_category();
+
+        write_messages(&mut store, &category, 3);
+        let messages = store.get(&category, None).unwrap();
+        let third = &messages[2];
+
+        let reset = reset_position(
+            &mut store,
+            &category,
+            None,
+            ResetTo::Time(third.time.unwrap()),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(third.global_position.unwrap() - 1, reset.position);
+        assert_eq!(1, reset.messages_to_process);
+    }
+
+    #[test]
+    fn dry_runs_leave_the_position_alone() {
+        let mut store = controls::message_store();
+        let category = stream_name::controls::unique_category();
+
+        write_messages(&mut store, &category, 2);
+
+        let reset = reset_position(&mut store, &category, None, ResetTo::Start, true).unwrap();
+
+        assert!(reset.dry_run);
+        assert_eq!(2, reset.messages_to_process);
+        assert_eq!(
+            None,
+            consumer_status(&mut store, &category, None)
+                .unwrap()
+                .position
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use crate::consumer::entity_store::{EntityStore, EntityStoreEntity};
+use crate::consumer::write_message::WriteMessage;
+use crate::Error;
+
+pub trait RetryOnConflict<T: EntityStoreEntity>: EntityStore<T> + WriteMessage {
+    // Fetches the entity and runs `f` with it and its version, fetching and
+    // running again when `f` fails with an expected version error
+    fn retry_on_conflict<F, R>(
+        &mut self,
+        identity: &str,
+        retries: usize,
+        mut f: F,
+    ) -> Result<R, Error>
+    where
+        F: FnMut(&mut Self, T, i64) -> Result<R, Error>,
+    {
+        let mut attempts = 0;
+
+        loop {
+            let (entity, version) = self.fetch_with_version(identity)?;
+
+            match f(self, entity, version) {
+                Err(Error::ExpectedVersion { .. }) if attempts < retries => attempts += 1,
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<T, S> RetryOnConflict<T> for S
+where
+    T: EntityStoreEntity,
+    S: EntityStore<T> + WriteMessage,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::consumer::controls::writer;
+    use crate::consumer::entity_store::{EntityBuilder, EntityStoreEntity};
+    use crate::consumer::write_message::WriteMessage;
+    use crate::message_store::{controls, MessageData, Put};
+    use crate::messaging::controls::message;
+    use crate::{identity, stream_name, Error};
+
+    use super::RetryOnConflict;
+
+    #[derive(Serialize, Deserialize, Default, Clone)]
+    struct Counter {
+        count: i64,
+    }
+
+    #[derive(Default)]
+    struct CounterProjector(Counter);
+
+    impl EntityBuilder<Counter> for CounterProjector {
+        fn initialize(&mut self, base_entity: Counter) {
+            self.0 = base_entity;
+        }
+
+        fn apply(&mut self, _message_data: MessageData) {
+            self.0.count += 1;
+        }
+
+        fn entity(&mut self) -> Counter {
+            self.0.clone()
+        }
+    }
+
+    impl EntityStoreEntity for Counter {
+        type Projector = CounterProjector;
+
+        fn get_projector() -> Self::Projector {
+            CounterProjector::default()
+        }
+    }
+
+    #[test]
+    fn retries_when_the_stream_has_moved_on() {
+        let mut writer = writer();
+        let id = identity::uuid().to_string();
+        let stream_name = stream_name!(&writer.category, id = &id);
+        let mut other = controls::message_store();
+        let mut attempts = 0;
+
+        let counter: Counter = writer
+            .retry_on_conflict(&id, 1, |writer, counter: Counter, version| {
+                attempts += 1;
+
+                if attempts == 1 {
+                    let _: MessageData = other
+                        .put(&controls::new_example(), &stream_name, None)
+                        .unwrap();
+                }
+
+                WriteMessage::write(writer, &message::new::event(), &id, Some(version))?;
+
+                Ok(counter)
+            })
+            .unwrap();
+
+        assert_eq!(2, attempts);
+        assert_eq!(1, counter.count);
+    }
+
+    #[test]
+    fn gives_up_after_the_retry_limit() {
+        let mut writer = writer();
+        let id = identity::uuid().to_string();
+        let mut attempts = 0;
+
+        let result: Result<MessageData, Error> =
+            writer.retry_on_conflict(&id, 2, |writer, _: Counter, version| {
+                attempts += 1;
+
+                WriteMessage::write(writer, &message::new::event(), &id, Some(version + 1))
+            });
+
+        assert_eq!(3, attempts);
+        assert!(matches!(result, Err(Error::ExpectedVersion { .. })));
+    }
+}
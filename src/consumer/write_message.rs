@@ -1,8 +1,9 @@
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-// use crate::message_store::MessageData;
-use crate::messaging::{write::Write, Message};
+use crate::message_store::{MessageData, Put};
+use crate::messaging::write::{stamp, Write};
+use crate::messaging::{Message, Metadata};
 use crate::{stream_name, Error, MessageStore};
 
 pub trait WriteMessage {
@@ -14,7 +15,7 @@ pub trait WriteMessage {
         message: &Message<T>,
         identity: &str,
         expected_version: Option<i64>,
-    ) -> Result<(), Error>
+    ) -> Result<MessageData, Error>
     where
         T: Serialize + DeserializeOwned + Default,
     {
@@ -26,7 +27,11 @@ pub trait WriteMessage {
         )
     }
 
-    fn write_initial<T>(&mut self, message: &Message<T>, identity: &str) -> Result<(), Error>
+    fn write_initial<T>(
+        &mut self,
+        message: &Message<T>,
+        identity: &str,
+    ) -> Result<MessageData, Error>
     where
         T: Serialize + DeserializeOwned + Default,
     {
@@ -34,4 +39,78 @@ pub trait WriteMessage {
         self.get_store()
             .write_initial(message, &stream_name!(category, id = identity))
     }
+
+    // Writes to another category's stream, such as a command stream, with
+    // the correlation stream set to this category's stream for `identity` so
+    // that replies can be consumed with `Settings::correlated`
+    fn write_correlated<T>(
+        &mut self,
+        message: &Message<T>,
+        stream_name: &str,
+        identity: &str,
+    ) -> Result<MessageData, Error>
+    where
+        T: Serialize + DeserializeOwned + Default,
+    {
+        let category = self.get_category();
+        let mut data = message.as_message_data();
+
+        let mut metadata: Metadata = serde_json::from_value(data.metadata)?;
+        metadata.correlate(&stream_name!(category, id = identity));
+        data.metadata = serde_json::to_value(&metadata)?;
+
+        let store = self.get_store();
+        stamp(&mut data, store.clock.as_ref())?;
+
+        store.put(&data, stream_name, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consumer::controls::{writer, Writer};
+    use crate::message_store::{controls, Get};
+    use crate::messaging::controls::message;
+    use crate::messaging::Metadata;
+    use crate::{clock, identity, stream_name, FixedClock};
+
+    use super::WriteMessage;
+
+    #[test]
+    fn correlates_written_messages_to_its_category() {
+        let mut writer = writer();
+        let identity = identity::uuid().to_string();
+        let command_stream = stream_name::controls::unique_example();
+
+        writer
+            .write_correlated(&message::new::command(), &command_stream, &identity)
+            .unwrap();
+
+        let written = writer.store.get_last(&command_stream).unwrap().unwrap();
+        let metadata: Metadata = serde_json::from_value(written.metadata).unwrap();
+
+        assert_eq!(
+            Some(stream_name!(&writer.category, id = &identity)),
+            metadata.correlation_stream_name
+        );
+    }
+
+    #[test]
+    fn stamps_correlated_messages_with_the_stores_clock() {
+        let mut writer = Writer {
+            store: controls::message_store().with_clock(FixedClock::new(clock::controls::time())),
+            ..writer()
+        };
+        let identity = identity::uuid().to_string();
+        let command_stream = stream_name::controls::unique_example();
+
+        writer
+            .write_correlated(&message::new::command(), &command_stream, &identity)
+            .unwrap();
+
+        let written = writer.store.get_last(&command_stream).unwrap().unwrap();
+        let metadata: Metadata = serde_json::from_value(written.metadata).unwrap();
+
+        assert_eq!(Some(clock::controls::time()), metadata.time);
+    }
 }
@@ -1,5 +1,8 @@
+use std::error::Error as _;
 use std::io;
 
+use postgres::error::SqlState;
+
 pub use chrono::{DateTime, Utc};
 pub use serde_json::Value as Json;
 pub use uuid::Uuid;
@@ -8,35 +11,163 @@ use thiserror::Error;
 
 pub use message_store::MessageStore;
 
-pub use crate::clock::Clock;
+pub use crate::clock::{Clock, FixedClock, ManualClock, SystemClock};
+pub use evt_macros::handlers;
+
+// Lets code generated by evt-macros refer to `::evt` from within this crate
+extern crate self as evt;
 
-mod clock;
+pub mod clock;
+pub mod component_host;
 pub mod consumer;
 pub mod db;
 pub mod identity;
 pub mod message_store;
+mod metrics;
 #[macro_use]
 pub mod stream_name;
-mod messaging;
+pub mod messaging;
+#[cfg(feature = "tracing")]
+pub mod trace;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("error from postgres")]
+    #[error("error from postgres: {0}")]
     PgError(#[from] postgres::Error),
-    #[error("postgres io error")]
+    #[error("postgres io error: {0}")]
     PgIoError(#[from] io::Error),
-    #[error("serialization error")]
+    #[error("serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
-    #[error("multiple messages found")]
+    #[error("multiple messages found in {0}")]
     MultipleMessages(String),
-    #[error("{0}")]
-    ExpectedVersion(String),
-    #[error("missing field in message data")]
-    MissingField,
-    #[error("stream name error")]
-    StreamName,
-    #[error("invalid message type")]
-    MessageType,
-    #[error("consumer error")]
-    ConsumerError,
+    #[error(
+        "wrong expected version: {expected} (stream: {stream_name}, stream version: {actual})"
+    )]
+    ExpectedVersion {
+        stream_name: String,
+        expected: i64,
+        actual: i64,
+    },
+    #[error("message {0} has already been written")]
+    DuplicateMessage(Uuid),
+    #[error("could not decode the message at position {position} of {stream_name}: {reason}")]
+    Decode {
+        stream_name: String,
+        position: i64,
+        reason: String,
+    },
+    #[error("missing {0} in message data")]
+    MissingField(&'static str),
+    #[error("invalid stream name {stream_name}: {reason}")]
+    StreamName { stream_name: String, reason: String },
+    #[error("expected a {expected} message, found {found}")]
+    MessageType { expected: String, found: String },
+    #[error("message DB conditions are not enabled, set message_store.sql_condition to on")]
+    SqlConditionDisabled,
+    #[error("component {0} panicked")]
+    ComponentPanicked(String),
+}
+
+impl Error {
+    // Whether trying again could succeed, as when the connection drops or a
+    // write conflicts with another, rather than the same attempt failing the
+    // same way. I/O errors outside of postgres, such as reading an export
+    // file, aren't.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::PgError(e) => match e.code() {
+                Some(code) => is_retryable_state(code),
+                None => is_connection_failure(e),
+            },
+            Error::ExpectedVersion { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+// Errors without a state are connection failures when they come from I/O, or
+// when the connection was already closed, which postgres only tells apart by
+// its message
+fn is_connection_failure(error: &postgres::Error) -> bool {
+    match error.source() {
+        Some(source) => source.is::<io::Error>(),
+        None => error.to_string() == "connection closed",
+    }
+}
+
+fn is_retryable_state(code: &SqlState) -> bool {
+    // Class 08 is connection exceptions
+    code.code().starts_with("08")
+        || [
+            SqlState::T_R_SERIALIZATION_FAILURE,
+            SqlState::T_R_DEADLOCK_DETECTED,
+            SqlState::TOO_MANY_CONNECTIONS,
+            SqlState::ADMIN_SHUTDOWN,
+            SqlState::CRASH_SHUTDOWN,
+            SqlState::CANNOT_CONNECT_NOW,
+        ]
+        .contains(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_failures_and_conflicts_are_retryable() {
+        let conflict = Error::ExpectedVersion {
+            stream_name: String::from("someStream-1"),
+            expected: 1,
+            actual: 2,
+        };
+
+        assert!(conflict.is_retryable());
+        assert!(is_retryable_state(&SqlState::CONNECTION_FAILURE));
+        assert!(is_retryable_state(&SqlState::T_R_SERIALIZATION_FAILURE));
+    }
+
+    #[test]
+    fn mistakes_are_not_retryable() {
+        let message_type = Error::MessageType {
+            expected: String::from("Deposited"),
+            found: String::from("Withdrawn"),
+        };
+
+        let io = io::Error::new(io::ErrorKind::NotFound, "no such file");
+
+        assert!(!message_type.is_retryable());
+        assert!(!Error::MissingField("id").is_retryable());
+        assert!(!Error::PgIoError(io).is_retryable());
+        assert!(!is_retryable_state(&SqlState::UNIQUE_VIOLATION));
+        assert!(!is_retryable_state(&SqlState::SYNTAX_ERROR));
+    }
+
+    #[test]
+    fn postgres_errors_are_classified_by_their_state() {
+        let mut client = db::build();
+        let syntax = client.execute("SELEC 1", &[]).unwrap_err();
+
+        assert!(!Error::from(syntax).is_retryable());
+    }
+
+    #[test]
+    fn terminated_connections_are_retryable() {
+        let mut client = db::build();
+        let pid: i32 = client
+            .query_one("SELECT pg_backend_pid()", &[])
+            .unwrap()
+            .get(0);
+
+        db::build()
+            .execute("SELECT pg_terminate_backend($1)", &[&pid])
+            .unwrap();
+
+        // Depending on timing, the first query after termination sees the
+        // shutdown or that the connection is closed, and the ones after the latter
+        for _ in 0..2 {
+            let error = client.execute("SELECT 1", &[]).unwrap_err();
+
+            assert!(Error::from(error).is_retryable());
+        }
+    }
 }
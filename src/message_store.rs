@@ -1,9 +1,17 @@
+pub mod all;
+pub mod condition;
 pub mod controls;
 mod core;
 pub mod get;
 pub mod put;
+pub mod read;
 pub mod tools;
+pub mod unit_of_work;
 
+pub use self::all::{Filter, ALL};
+pub use self::condition::Condition;
 pub use self::core::{MessageData, MessageStore, Settings, INITIAL};
 pub use self::get::Get;
 pub use self::put::Put;
+pub use self::read::{Read, Reader};
+pub use self::unit_of_work::UnitOfWork;
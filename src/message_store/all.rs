@@ -0,0 +1,183 @@
+use postgres::Client;
+
+use crate::message_store::get::{read_messages, COLUMNS};
+use crate::message_store::read::BATCH_SIZE_DEFAULT;
+use crate::message_store::{MessageData, Settings};
+use crate::{metrics, Error};
+
+use std::time::Instant;
+
+// Stands in for a category to read every stream in the store, in global
+// position order, for read models and change data capture that span
+// categories. Streams whose names start with it, such as the positions of a
+// consumer of the whole store, are never read.
+pub const ALL: &str = "$all";
+
+// Categories start at global position 1, and so does the store
+const STARTING_GLOBAL_POSITION: i64 = 1;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Filter {
+    pub message_types: Option<Vec<String>>,
+    pub categories: Option<Vec<String>>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn message_types(self, message_types: &[&str]) -> Self {
+        Self {
+            message_types: Some(message_types.iter().map(|t| String::from(*t)).collect()),
+            ..self
+        }
+    }
+
+    pub fn categories(self, categories: &[&str]) -> Self {
+        Self {
+            categories: Some(categories.iter().map(|c| String::from(*c)).collect()),
+            ..self
+        }
+    }
+}
+
+// Reads a batch of messages from the global position, using the batch size of
+// the settings. Message DB has no function for this, so the other settings,
+// such as conditions and consumer groups, don't apply.
+pub fn get_all(
+    client: &mut Client,
+    settings: &Settings,
+    filter: &Filter,
+    position: Option<i64>,
+) -> Result<Vec<MessageData>, Error> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("evt.get", stream_name = ALL, position).entered();
+
+    let started = Instant::now();
+
+    let q = format!(
+        "SELECT {} \
+         FROM messages \
+         WHERE global_position >= $1 \
+           AND stream_name NOT LIKE $2::varchar || '%' \
+           AND ($3::varchar[] IS NULL OR type = ANY($3)) \
+           AND ($4::varchar[] IS NULL OR category(stream_name) = ANY($4)) \
+         ORDER BY global_position \
+         LIMIT $5",
+        COLUMNS
+    );
+
+    let messages = read_messages(
+        client,
+        &q,
+        &[
+            &position.unwrap_or(STARTING_GLOBAL_POSITION),
+            &ALL,
+            &filter.message_types,
+            &filter.categories,
+            &settings.batch_size.unwrap_or(BATCH_SIZE_DEFAULT),
+        ],
+        settings.skip_malformed,
+    )?;
+
+    metrics::read(ALL, messages.len(), started.elapsed());
+
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message_store::{controls, Get, MessageData, MessageStore, Put};
+    use crate::{identity, stream_name};
+
+    use super::*;
+
+    fn write_message(store: &mut MessageStore, category: &str, message_type: &str) -> i64 {
+        let stream_name = stream_name!(category, id = identity::uuid());
+        let mut data = controls::new_example();
+        data.message_type = String::from(message_type);
+
+        let _: MessageData = store.put(&data, &stream_name, None).unwrap();
+
+        store
+            .get_last(&stream_name)
+            .unwrap()
+            .unwrap()
+            .global_position
+            .unwrap()
+    }
+
+    #[test]
+    fn gets_messages_across_categories_in_global_position_order() {
+        let mut store = controls::message_store();
+        let first = stream_name::controls::unique_category();
+        let second = stream_name::controls::unique_category();
+
+        let position = write_message(&mut store, &first, "SomeMessage");
+        write_message(&mut store, &second, "SomeMessage");
+
+        let filter = Filter::new().categories(&[&first, &second]);
+        let messages = store.get_all(Some(position), &filter).unwrap();
+
+        assert_eq!(2, messages.len());
+        assert_eq!(Some(position), messages[0].global_position);
+        assert!(messages[0].global_position < messages[1].global_position);
+        assert_eq!(
+            first,
+            stream_name::get_category(messages[0].stream_name.as_deref().unwrap())
+        );
+    }
+
+    #[test]
+    fn filters_by_message_type() {
+        let mut store = controls::message_store();
+        let category = stream_name::controls::unique_category();
+
+        let position = write_message(&mut store, &category, "SomeMessage");
+        write_message(&mut store, &category, "OtherMessage");
+
+        let filter = Filter::new()
+            .categories(&[&category])
+            .message_types(&["OtherMessage"]);
+        let messages = store.get_all(Some(position), &filter).unwrap();
+
+        assert_eq!(1, messages.len());
+        assert_eq!("OtherMessage", messages[0].message_type);
+    }
+
+    #[test]
+    fn limits_reads_to_the_batch_size() {
+        let mut store = controls::message_store();
+        store.settings.batch_size = Some(1);
+        let category = stream_name::controls::unique_category();
+
+        let position = write_message(&mut store, &category, "SomeMessage");
+        write_message(&mut store, &category, "SomeMessage");
+
+        let messages = store.get_all(Some(position), &Filter::new()).unwrap();
+
+        assert_eq!(1, messages.len());
+    }
+
+    #[test]
+    fn never_gets_streams_of_the_whole_store() {
+        let mut store = controls::message_store();
+        let stream_name = format!("{}:position", ALL);
+
+        let _: MessageData = store
+            .put(&controls::new_example(), &stream_name, None)
+            .unwrap();
+        let position = store
+            .get_last(&stream_name)
+            .unwrap()
+            .unwrap()
+            .global_position;
+
+        let messages = store.get_all(position, &Filter::new()).unwrap();
+
+        assert!(messages
+            .iter()
+            .all(|message| message.stream_name.as_deref() != Some(stream_name.as_str())));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{DateTime, Utc};
+
+// Builds the SQL condition Message DB adds to its `WHERE` clause when reading,
+// quoting every value so none of them can change the query. Conditions are
+// combined with `AND`.
+//
+// Message DB only applies conditions when `message_store.sql_condition` is on
+// for the session, role or database.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Condition {
+    clauses: Vec<String>,
+}
+
+impl Condition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn message_type(self, message_type: &str) -> Self {
+        self.message_types(&[message_type])
+    }
+
+    // No types at all matches no messages, as `IN ()` isn't valid SQL
+    pub fn message_types(self, message_types: &[&str]) -> Self {
+        if message_types.is_empty() {
+            return self.clause(String::from("false"));
+        }
+
+        let message_types: Vec<String> = message_types.iter().map(|t| quote(t)).collect();
+
+        self.clause(format!("type IN ({})", message_types.join(", ")))
+    }
+
+    // Compares the metadata field's text, so non-string values match their
+    // JSON representation, such as `5` or `true`
+    pub fn metadata(self, field: &str, value: &str) -> Self {
+        self.clause(format!("metadata->>{} = {}", quote(field), quote(value)))
+    }
+
+    pub fn data(self, field: &str, value: &str) -> Self {
+        self.clause(format!("data->>{} = {}", quote(field), quote(value)))
+    }
+
+    pub fn time_from(self, time: DateTime<Utc>) -> Self {
+        self.clause(format!("time >= {}", quote_time(time)))
+    }
+
+    pub fn time_until(self, time: DateTime<Utc>) -> Self {
+        self.clause(format!("time < {}", quote_time(time)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clauses.is_empty()
+    }
+
+    pub fn to_sql(&self) -> Option<String> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.clauses.join(" AND "))
+        }
+    }
+
+    fn clause(mut self, clause: String) -> Self {
+        self.clauses.push(clause);
+        self
+    }
+}
+
+// Quotes like Postgres' quote_literal, so the value reads the same whether or
+// not standard_conforming_strings is on
+fn quote(value: &str) -> String {
+    let quoted = value.replace('\'', "''");
+
+    if quoted.contains('\\') {
+        format!("E'{}'", quoted.replace('\\', "\\\\"))
+    } else {
+        format!("'{}'", quoted)
+    }
+}
+
+// Message times are stored as UTC timestamps without a time zone
+fn quote_time(time: DateTime<Utc>) -> String {
+    quote(&time.naive_utc().format("%Y-%m-%d %H:%M:%S%.f").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::controls;
+    use crate::message_store::{controls as store_controls, Get, MessageData, Put, Settings};
+    use crate::{stream_name, Error};
+
+    use super::*;
+
+    #[test]
+    fn renders_nothing_without_clauses() {
+        assert_eq!(None, Condition::new().to_sql());
+    }
+
+    #[test]
+    fn combines_clauses() {
+        let condition = Condition::new()
+            .message_types(&["Deposited", "Withdrawn"])
+            .metadata("schema_version", "1")
+            .time_from(controls::time());
+
+        assert_eq!(
+            Some(String::from(
+                "type IN ('Deposited', 'Withdrawn') \
+                 AND metadata->>'schema_version' = '1' \
+                 AND time >= '2222-02-22 02:22:22'"
+            )),
+            condition.to_sql()
+        );
+    }
+
+    #[test]
+    fn matches_nothing_without_message_types() {
+        let condition = Condition::new().message_types(&[]);
+
+        assert_eq!(Some(String::from("false")), condition.to_sql());
+    }
+
+    #[test]
+    fn quotes_values() {
+        let condition = Condition::new()
+            .message_type("x' OR 'a'='a")
+            .data("path", "C:\\temp");
+
+        assert_eq!(
+            Some(String::from(
+                "type IN ('x'' OR ''a''=''a') AND data->>'path' = E'C:\\\\temp'"
+            )),
+            condition.to_sql()
+        );
+    }
+
+    #[test]
+    fn filters_messages_read() {
+        let mut store = store_controls::message_store();
+        store
+            .client
+            .batch_execute("SET message_store.sql_condition = on")
+            .unwrap();
+
+        let stream_name = stream_name::controls::unique_example();
+        let mut other = store_controls::new_example();
+        other.message_type = String::from("Other's");
+
+        let _: Vec<MessageData> = store
+            .put(
+                vec![&store_controls::new_example(), &other],
+                &stream_name,
+                None,
+            )
+            .unwrap();
+
+        store.settings = Settings {
+            condition: Condition::new().message_type("Other's").to_sql(),
+            ..Default::default()
+        };
+
+        let messages = store.get(&stream_name, None).unwrap();
+
+        assert_eq!(1, messages.len());
+        assert_eq!("Other's", messages[0].message_type);
+    }
+
+    #[test]
+    fn fails_clearly_when_the_server_does_not_allow_conditions() {
+        let mut store = store_controls::message_store();
+        store
+            .client
+            .batch_execute("SET message_store.sql_condition = off")
+            .unwrap();
+
+        store.settings = Settings {
+            condition: Condition::new().message_type("Event").to_sql(),
+            ..Default::default()
+        };
+
+        let result = store.get(&stream_name::controls::unique_example(), None);
+
+        assert!(matches!(result, Err(Error::SqlConditionDisabled)));
+    }
+}
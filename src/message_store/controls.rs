@@ -1,5 +1,5 @@
 use crate::message_store::core::{MessageData, MessageStore, Settings};
-use crate::{clock, db, identity, messaging, stream_name, Uuid};
+use crate::{clock, db, identity, messaging, stream_name, SystemClock, Uuid};
 
 pub fn settings() -> Settings {
     Settings {
@@ -11,6 +11,7 @@ pub fn message_store() -> MessageStore {
     MessageStore {
         client: db::build(),
         settings: settings(),
+        clock: Box::new(SystemClock),
     }
 }
 
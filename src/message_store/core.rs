@@ -1,14 +1,15 @@
-use crate::db;
 use crate::Json;
 use crate::Uuid;
+use crate::{db, Clock, SystemClock};
 use crate::{DateTime, Utc};
 use postgres::Client;
+use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 
 pub const INITIAL: Option<i64> = Some(-1);
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct MessageData {
     pub id: Option<Uuid>,
     pub message_type: String,
@@ -27,11 +28,17 @@ pub struct Settings {
     pub group_member: Option<i64>,
     pub group_size: Option<i64>,
     pub condition: Option<String>,
+    pub idempotent_writes: bool,
+    // Leaves malformed messages out of stream, category and whole store reads,
+    // logging each one, instead of failing with `Error::Decode`
+    pub skip_malformed: bool,
 }
 
 pub struct MessageStore {
     pub settings: Settings,
     pub client: Client,
+    // Stamps the metadata time of messages written without one
+    pub clock: Box<dyn Clock + Send>,
 }
 
 impl MessageStore {
@@ -39,6 +46,7 @@ impl MessageStore {
         MessageStore {
             settings: Settings::default(),
             client: db::build(),
+            clock: Box::new(SystemClock),
         }
     }
 
@@ -46,6 +54,14 @@ impl MessageStore {
         MessageStore {
             settings,
             client: db::build(),
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock<C: Clock + Send + 'static>(self, clock: C) -> Self {
+        Self {
+            clock: Box::new(clock),
+            ..self
         }
     }
 }
@@ -1,19 +1,47 @@
+use log::warn;
+use postgres::error::DbError;
 use postgres::types::ToSql;
-use postgres::Client;
+use postgres::{Client, Row};
 
+use crate::message_store::all::{get_all, Filter, ALL};
 use crate::message_store::{MessageData, MessageStore, Settings};
+use crate::messaging::Metadata;
 use crate::stream_name::is_category;
-use crate::Error;
+use crate::{metrics, Error};
 use crate::{DateTime, Json, Utc, Uuid};
 use chrono::NaiveDateTime;
 
+use std::error::Error as _;
+use std::fmt::Display;
+use std::time::Instant;
+
 type Params<'a> = &'a [&'a (dyn ToSql + Sync)];
 type DataResult = Result<Vec<MessageData>, Error>;
 type SingleResult = Result<Option<MessageData>, Error>;
+type VersionResult = Result<Option<i64>, Error>;
+
+// What Message DB raises when reading with a condition while
+// message_store.sql_condition is off
+const SQL_CONDITION_DISABLED: &str = "Retrieval with SQL condition is not activated";
+
+// Columns of the messages table in the order `get_messages` maps them, for
+// reads Message DB has no function for
+pub(crate) const COLUMNS: &str = "id::varchar, stream_name::varchar, type::varchar, position, \
+                                  global_position, data::varchar, metadata::varchar, time";
 
 pub trait Get {
     fn get(&mut self, stream_name: &str, position: Option<i64>) -> DataResult;
     fn get_last(&mut self, stream_name: &str) -> SingleResult;
+    fn stream_version(&mut self, stream_name: &str) -> VersionResult;
+    fn get_all(&mut self, position: Option<i64>, filter: &Filter) -> DataResult;
+    fn get_backward(&mut self, stream_name: &str, count: i64) -> DataResult;
+    fn get_range(&mut self, stream_name: &str, from: i64, to: i64) -> DataResult;
+    fn get_by_id(&mut self, id: Uuid) -> SingleResult;
+    fn causation_chain(&mut self, message: &MessageData) -> DataResult;
+
+    fn stream_exists(&mut self, stream_name: &str) -> Result<bool, Error> {
+        Ok(self.stream_version(stream_name)?.is_some())
+    }
 }
 
 impl Get for MessageStore {
@@ -24,6 +52,40 @@ impl Get for MessageStore {
     fn get_last(&mut self, stream_name: &str) -> SingleResult {
         get_last(&mut self.client, stream_name)
     }
+
+    fn stream_version(&mut self, stream_name: &str) -> VersionResult {
+        stream_version(&mut self.client, stream_name)
+    }
+
+    fn get_all(&mut self, position: Option<i64>, filter: &Filter) -> DataResult {
+        get_all(&mut self.client, &self.settings, filter, position)
+    }
+
+    fn get_backward(&mut self, stream_name: &str, count: i64) -> DataResult {
+        get_backward(&mut self.client, stream_name, count)
+    }
+
+    fn get_range(&mut self, stream_name: &str, from: i64, to: i64) -> DataResult {
+        get_range(&mut self.client, stream_name, from, to)
+    }
+
+    fn get_by_id(&mut self, id: Uuid) -> SingleResult {
+        get_by_id(&mut self.client, id)
+    }
+
+    fn causation_chain(&mut self, message: &MessageData) -> DataResult {
+        causation_chain(&mut self.client, message)
+    }
+}
+
+// The rows of a read, including the malformed ones that were skipped, which
+// still count towards the batch size and move the position on
+#[derive(Debug, Default)]
+pub(crate) struct Batch {
+    pub(crate) messages: Vec<MessageData>,
+    pub(crate) rows: usize,
+    pub(crate) last_position: Option<i64>,
+    pub(crate) last_global_position: Option<i64>,
 }
 
 pub fn get(
@@ -32,10 +94,115 @@ pub fn get(
     stream_name: &str,
     position: Option<i64>,
 ) -> DataResult {
-    if is_category(stream_name) {
+    get_batch(client, settings, stream_name, position).map(|batch| batch.messages)
+}
+
+pub(crate) fn get_batch(
+    client: &mut Client,
+    settings: &Settings,
+    stream_name: &str,
+    position: Option<i64>,
+) -> Result<Batch, Error> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("evt.get", stream_name, position).entered();
+
+    let started = Instant::now();
+
+    let batch = if is_category(stream_name) {
         get_category(client, settings, stream_name, position)
     } else {
-        get_stream(client, settings, stream_name, position)
+        get_stream_batch(client, settings, stream_name, position)
+    }?;
+
+    metrics::read(stream_name, batch.messages.len(), started.elapsed());
+
+    Ok(batch)
+}
+
+// The last `count` messages of a stream, newest first. Categories are read
+// backwards by global position.
+pub fn get_backward(client: &mut Client, stream_name: &str, count: i64) -> DataResult {
+    let (scope, position) = stream_scope(stream_name);
+    let q = format!(
+        "SELECT {} FROM messages WHERE {} ORDER BY {} DESC LIMIT $2",
+        COLUMNS, scope, position
+    );
+
+    get_messages(client, &q, &[&String::from(stream_name), &count])
+}
+
+// The messages of a stream from position `from` to `to`, inclusive. Category
+// ranges are of global positions.
+pub fn get_range(client: &mut Client, stream_name: &str, from: i64, to: i64) -> DataResult {
+    let (scope, position) = stream_scope(stream_name);
+    let q = format!(
+        "SELECT {columns} FROM messages \
+         WHERE {scope} AND {position} BETWEEN $2 AND $3 \
+         ORDER BY {position}",
+        columns = COLUMNS,
+        scope = scope,
+        position = position
+    );
+
+    get_messages(client, &q, &[&String::from(stream_name), &from, &to])
+}
+
+pub fn get_by_id(client: &mut Client, id: Uuid) -> SingleResult {
+    let q = format!("SELECT {} FROM messages WHERE id = $1", COLUMNS);
+
+    Ok(get_messages(client, &q, &[&id])?.pop())
+}
+
+// The messages that led to the message, found by following each one's
+// causation metadata back to one that wasn't caused by another. The chain is
+// oldest first and ends with the message itself.
+pub fn causation_chain(client: &mut Client, message: &MessageData) -> DataResult {
+    let mut chain = vec![message.clone()];
+
+    while let Some(cause) = get_cause(client, &chain[chain.len() - 1])? {
+        // Hand written metadata could make a cycle
+        if chain.iter().any(|message| message.id == cause.id) {
+            break;
+        }
+
+        chain.push(cause);
+    }
+
+    chain.reverse();
+
+    Ok(chain)
+}
+
+fn get_cause(client: &mut Client, message: &MessageData) -> SingleResult {
+    if !message.metadata.is_object() {
+        return Ok(None);
+    }
+
+    let metadata: Metadata = serde_json::from_value(message.metadata.clone())?;
+
+    match (
+        metadata.causation_message_stream_name,
+        metadata.causation_message_position,
+    ) {
+        (Some(stream_name), Some(position)) => {
+            let q = format!(
+                "SELECT {} FROM messages WHERE stream_name = $1::varchar AND position = $2",
+                COLUMNS
+            );
+
+            Ok(get_messages(client, &q, &[&stream_name, &position])?.pop())
+        }
+        _ => Ok(None),
+    }
+}
+
+// Which messages belong to the stream, with the stream name as $1, and the
+// column they're ordered by
+fn stream_scope(stream_name: &str) -> (&'static str, &'static str) {
+    if is_category(stream_name) {
+        ("category(stream_name) = $1::varchar", "global_position")
+    } else {
+        ("stream_name = $1::varchar", "position")
     }
 }
 
@@ -55,16 +222,106 @@ pub fn get_last(client: &mut Client, stream_name: &str) -> SingleResult {
     }
 }
 
+pub fn stream_version(client: &mut Client, stream_name: &str) -> VersionResult {
+    let q = "SELECT stream_version($1::varchar)";
+    let row = client.query_one(q, &[&String::from(stream_name)])?;
+
+    Ok(row.get(0))
+}
+
+// The global position of the newest message in the category, which is how far
+// a consumer of the category can get
+pub fn last_global_position(client: &mut Client, category: &str) -> VersionResult {
+    let q = format!(
+        "SELECT max(global_position) FROM messages WHERE {}",
+        category_scope(category)
+    );
+    let row = client.query_one(q.as_str(), &[&String::from(category)])?;
+
+    Ok(row.get(0))
+}
+
+// The messages a consumer of the category reads, with the category as $1
+fn category_scope(category: &str) -> &'static str {
+    if category == ALL {
+        "stream_name NOT LIKE $1::varchar || '%'"
+    } else {
+        "category(stream_name) = $1::varchar"
+    }
+}
+
+// The global position of the first message in the category written at or
+// after the time
+pub fn first_global_position_since(
+    client: &mut Client,
+    category: &str,
+    time: DateTime<Utc>,
+) -> VersionResult {
+    let q = format!(
+        "SELECT min(global_position) FROM messages WHERE {} AND time >= $2",
+        category_scope(category)
+    );
+    let row = client.query_one(q.as_str(), &[&String::from(category), &time.naive_utc()])?;
+
+    Ok(row.get(0))
+}
+
+// How many messages in the category come after the global position
+pub fn count_since(
+    client: &mut Client,
+    category: &str,
+    global_position: i64,
+) -> Result<i64, Error> {
+    let q = format!(
+        "SELECT count(*) FROM messages WHERE {} AND global_position > $2",
+        category_scope(category)
+    );
+    let row = client.query_one(q.as_str(), &[&String::from(category), &global_position])?;
+
+    Ok(row.get(0))
+}
+
+// Mirrors the check Message DB makes before applying a condition
+pub fn sql_condition_enabled(client: &mut Client) -> Result<bool, Error> {
+    let q = "SELECT current_setting('message_store.sql_condition', true)";
+    let row = client.query_one(q, &[])?;
+    let setting: Option<String> = row.get(0);
+
+    Ok(matches!(setting.as_deref(), Some(setting) if setting != "off"))
+}
+
+fn condition_error(error: Error) -> Error {
+    match &error {
+        Error::PgError(e)
+            if e.source()
+                .and_then(|e| e.downcast_ref::<DbError>())
+                .is_some_and(|e| e.message() == SQL_CONDITION_DISABLED) =>
+        {
+            Error::SqlConditionDisabled
+        }
+        _ => error,
+    }
+}
+
 pub fn get_stream(
     client: &mut Client,
     settings: &Settings,
     stream_name: &str,
     position: Option<i64>,
 ) -> DataResult {
+    get_stream_batch(client, settings, stream_name, position).map(|batch| batch.messages)
+}
+
+fn get_stream_batch(
+    client: &mut Client,
+    settings: &Settings,
+    stream_name: &str,
+    position: Option<i64>,
+) -> Result<Batch, Error> {
     let q = "SELECT * FROM \
              get_stream_messages($1::varchar, $2::bigint, $3::bigint, $4::varchar)";
 
-    get_messages(
+    read_batch(
         client,
         q,
         &[
@@ -73,7 +330,9 @@ pub fn get_stream(
             &settings.batch_size,
             &settings.condition,
         ],
+        settings.skip_malformed,
     )
+    .map_err(condition_error)
 }
 
 fn get_category(
@@ -81,13 +340,13 @@ fn get_category(
     settings: &Settings,
     stream_name: &str,
     position: Option<i64>,
-) -> DataResult {
+) -> Result<Batch, Error> {
     let q = "SELECT * \
              FROM get_category_messages($1::varchar, $2::bigint, $3::bigint, \
                                         $4::varchar, $5::bigint, $6::bigint, \
                                         $7::varchar)";
 
-    get_messages(
+    read_batch(
         client,
         q,
         &[
@@ -99,37 +358,106 @@ fn get_category(
             &settings.group_size,
             &settings.condition,
         ],
+        settings.skip_malformed,
     )
+    .map_err(condition_error)
 }
 
-fn get_messages(client: &mut Client, query: &str, params: Params) -> DataResult {
-    let results = client
-        .query(query, params)?
-        .iter()
-        .map(|row| MessageData {
-            id: uuid_result(row.get(0)),
-            stream_name: row.get(1),
-            message_type: row.get(2),
-            position: row.get(3),
-            global_position: row.get(4),
-            data: json_result(row.get(5)),
-            metadata: json_result(row.get(6)),
-            time: time_result(row.get(7)),
-        })
-        .collect();
+pub(crate) fn get_messages(client: &mut Client, query: &str, params: Params) -> DataResult {
+    read_messages(client, query, params, false)
+}
 
-    Ok(results)
+pub(crate) fn read_messages(
+    client: &mut Client,
+    query: &str,
+    params: Params,
+    skip_malformed: bool,
+) -> DataResult {
+    read_batch(client, query, params, skip_malformed).map(|batch| batch.messages)
 }
 
-fn uuid_result(result: &str) -> Option<Uuid> {
-    match Uuid::parse_str(result) {
-        Ok(uuid) => Some(uuid),
-        Err(_) => None,
+// Fails on the first malformed row, unless skipping them, when each one is
+// reported and left out
+pub(crate) fn read_batch(
+    client: &mut Client,
+    query: &str,
+    params: Params,
+    skip_malformed: bool,
+) -> Result<Batch, Error> {
+    let mut batch = Batch::default();
+
+    for row in client.query(query, params)? {
+        batch.rows += 1;
+        batch.last_position = row.try_get(3).ok();
+        batch.last_global_position = row.try_get(4).ok();
+
+        match decode(&row) {
+            Ok(message) => batch.messages.push(message),
+            Err(Error::Decode {
+                stream_name,
+                position,
+                reason,
+            }) if skip_malformed => {
+                warn!(
+                    "Skipping malformed message at position {} of {}: {}",
+                    position, stream_name, reason
+                );
+
+                metrics::malformed(&stream_name);
+            }
+            Err(e) => return Err(e),
+        }
     }
+
+    Ok(batch)
 }
 
-fn json_result(result: &str) -> Json {
-    serde_json::from_str(result).unwrap()
+fn decode(row: &Row) -> Result<MessageData, Error> {
+    let stream_name: String = row.try_get(1)?;
+    let position: i64 = row.try_get(3)?;
+
+    let malformed = |column: &str, reason: &dyn Display| Error::Decode {
+        stream_name: stream_name.clone(),
+        position,
+        reason: format!("{}: {}", column, reason),
+    };
+
+    let id: &str = row.try_get(0).map_err(|e| malformed("id", &e))?;
+    let id = Uuid::parse_str(id).map_err(|e| malformed("id", &e))?;
+
+    let data = row
+        .try_get(5)
+        .map_err(|e| malformed("data", &e))
+        .and_then(|data| json_result(data).map_err(|e| malformed("data", &e)))?;
+
+    let metadata = row
+        .try_get(6)
+        .map_err(|e| malformed("metadata", &e))
+        .and_then(|metadata| json_result(metadata).map_err(|e| malformed("metadata", &e)))?;
+
+    Ok(MessageData {
+        id: Some(id),
+        message_type: row.try_get(2).map_err(|e| malformed("type", &e))?,
+        global_position: row
+            .try_get(4)
+            .map_err(|e| malformed("global_position", &e))?,
+        data,
+        metadata,
+        time: row
+            .try_get(7)
+            .map(time_result)
+            .map_err(|e| malformed("time", &e))?,
+        stream_name: Some(stream_name),
+        position: Some(position),
+    })
+}
+
+// Messages can be written without data or metadata
+fn json_result(result: Option<&str>) -> Result<Json, serde_json::Error> {
+    match result {
+        Some(result) => serde_json::from_str(result),
+        None => Ok(Json::Null),
+    }
 }
 
 fn time_result(result: NaiveDateTime) -> Option<DateTime<Utc>> {
@@ -138,8 +466,162 @@ fn time_result(result: NaiveDateTime) -> Option<DateTime<Utc>> {
 
 #[cfg(test)]
 mod tests {
-    use crate::message_store::{controls, Get, MessageData, Put, INITIAL};
-    use crate::stream_name;
+    use crate::message_store::{controls, Get, MessageData, MessageStore, Put, INITIAL};
+    use crate::{identity, stream_name};
+
+    use super::{last_global_position, read_messages};
+    use crate::{db, Error, Json};
+
+    const MALFORMED: &str = "SELECT 'not-an-id', 'someStream-1', 'SomeType', 3::bigint, \
+                                    11::bigint, '{}', 'not json', now()::timestamp";
+    const WELL_FORMED: &str =
+        "SELECT '5b8b3e5c-7a48-4b2b-9d2e-1f0f2a4c6d8e', 'someStream-1', 'SomeType', \
+                                      4::bigint, 12::bigint, NULL, '{}', now()::timestamp";
+
+    fn write_messages(store: &mut MessageStore, stream_name: &str, count: usize) {
+        let data: Vec<MessageData> = (0..count).map(|_| controls::new_example()).collect();
+
+        let _: Vec<MessageData> = store
+            .put(data.iter().collect(), stream_name, INITIAL)
+            .unwrap();
+    }
+
+    fn positions(messages: &[MessageData]) -> Vec<i64> {
+        messages.iter().map(|m| m.position.unwrap()).collect()
+    }
+
+    #[test]
+    fn malformed_messages_fail_with_where_they_are() {
+        let mut client = db::build();
+
+        let result = read_messages(&mut client, MALFORMED, &[], false);
+
+        match result {
+            Err(Error::Decode {
+                stream_name,
+                position,
+                reason,
+            }) => {
+                assert_eq!("someStream-1", stream_name);
+                assert_eq!(3, position);
+                assert!(reason.starts_with("id"));
+            }
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed_messages_can_be_skipped() {
+        let mut client = db::build();
+        let q = format!("{} UNION ALL {}", MALFORMED, WELL_FORMED);
+
+        let messages = read_messages(&mut client, &q, &[], true).unwrap();
+
+        assert_eq!(1, messages.len());
+        assert_eq!(Some(4), messages[0].position);
+        assert_eq!(Json::Null, messages[0].data);
+    }
+
+    #[test]
+    fn gets_a_message_by_id() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+        let mut data = controls::new_example();
+        data.id = Some(identity::uuid());
+
+        let _: MessageData = store.put(&data, &stream_name, INITIAL).unwrap();
+
+        let retrieved = store.get_by_id(data.id.unwrap()).unwrap().unwrap();
+
+        assert_eq!(Some(stream_name), retrieved.stream_name);
+        assert!(store.get_by_id(identity::uuid()).unwrap().is_none());
+    }
+
+    #[test]
+    fn follows_causation_back_to_the_first_message() {
+        let mut store = controls::message_store();
+        let mut previous: Option<(String, i64)> = None;
+
+        for _ in 0..3 {
+            let stream_name = stream_name::controls::unique_example();
+            let mut data = controls::new_example();
+
+            data.metadata = match &previous {
+                Some((stream_name, position)) => serde_json::json!({
+                    "causation_message_stream_name": stream_name,
+                    "causation_message_position": position,
+                }),
+                None => serde_json::json!({}),
+            };
+
+            let _: MessageData = store.put(&data, &stream_name, INITIAL).unwrap();
+            previous = Some((stream_name, 0));
+        }
+
+        let (stream_name, _) = previous.unwrap();
+        let last = store.get_last(&stream_name).unwrap().unwrap();
+
+        let chain = store.causation_chain(&last).unwrap();
+
+        assert_eq!(3, chain.len());
+        assert_eq!(last.id, chain[2].id);
+        assert_eq!(None, chain[0].metadata.get("causation_message_stream_name"));
+    }
+
+    #[test]
+    fn gets_the_last_messages_of_a_stream_newest_first() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+
+        write_messages(&mut store, &stream_name, 4);
+
+        let messages = store.get_backward(&stream_name, 2).unwrap();
+
+        assert_eq!(vec![3, 2], positions(&messages));
+    }
+
+    #[test]
+    fn gets_a_range_of_a_stream() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+
+        write_messages(&mut store, &stream_name, 5);
+
+        let messages = store.get_range(&stream_name, 1, 3).unwrap();
+
+        assert_eq!(vec![1, 2, 3], positions(&messages));
+        messages_eq(
+            &store.get_last(&stream_name).unwrap().unwrap(),
+            &store.get_range(&stream_name, 4, 10).unwrap()[0],
+        );
+    }
+
+    #[test]
+    fn gets_a_range_of_a_category_by_global_position() {
+        let mut store = controls::message_store();
+        let category = stream_name::controls::unique_category();
+
+        for _ in 0..3 {
+            write_messages(
+                &mut store,
+                &stream_name!(&category, id = identity::uuid()),
+                1,
+            );
+        }
+
+        let all = store.get(&category, None).unwrap();
+        let first = all[0].global_position.unwrap();
+        let second = all[1].global_position.unwrap();
+
+        let messages = store.get_range(&category, first, second).unwrap();
+
+        assert_eq!(2, messages.len());
+        assert_eq!(all[1].id, messages[1].id);
+
+        let backward = store.get_backward(&category, 1).unwrap();
+
+        assert_eq!(all[2].id, backward[0].id);
+    }
 
     #[test]
     fn gets_messages_from_stream() {
@@ -211,6 +693,33 @@ mod tests {
         assert!(retrieved.is_none());
     }
 
+    #[test]
+    fn stream_version_is_the_position_of_the_last_message() {
+        let mut store = controls::message_store();
+        let data: Vec<MessageData> = (0..2).map(|_| controls::new_example()).collect();
+        let stream_name = stream_name::controls::unique_example();
+
+        let _: Vec<MessageData> = store
+            .put(data.iter().collect(), stream_name.as_str(), INITIAL)
+            .unwrap();
+
+        let version = store.stream_version(stream_name.as_str()).unwrap();
+
+        assert_eq!(Some(1), version);
+        assert!(store.stream_exists(stream_name.as_str()).unwrap());
+    }
+
+    #[test]
+    fn an_empty_stream_has_no_version_and_does_not_exist() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+
+        let version = store.stream_version(stream_name.as_str()).unwrap();
+
+        assert!(version.is_none());
+        assert!(!store.stream_exists(stream_name.as_str()).unwrap());
+    }
+
     fn messages_eq(stored: &MessageData, retrieved: &MessageData) {
         assert_eq!(stored.id, retrieved.id);
         assert_eq!(stored.stream_name, retrieved.stream_name);
@@ -221,4 +730,31 @@ mod tests {
         assert_eq!(stored.metadata, retrieved.metadata);
         assert!(retrieved.time.is_some());
     }
+
+    #[test]
+    fn gets_the_last_global_position_of_a_category() {
+        let mut store = controls::message_store();
+        let category = stream_name::controls::unique_category();
+
+        assert_eq!(
+            None,
+            last_global_position(&mut store.client, &category).unwrap()
+        );
+
+        let stream_name = stream_name!(&category, id = identity::uuid());
+        let _: Vec<MessageData> = store
+            .put(
+                vec![&controls::new_example(), &controls::new_example()],
+                &stream_name,
+                None,
+            )
+            .unwrap();
+
+        let last = store.get_last(&stream_name).unwrap().unwrap();
+
+        assert_eq!(
+            last.global_position,
+            last_global_position(&mut store.client, &category).unwrap()
+        );
+    }
 }
@@ -1,12 +1,23 @@
+use postgres::error::{DbError, SqlState};
 use postgres::types::ToSql;
-use postgres::GenericClient;
+use postgres::{GenericClient, Transaction};
 
 use crate::identity;
 use crate::message_store::core::{MessageData, MessageStore};
-use crate::Error;
+use crate::metrics;
+#[cfg(feature = "tracing")]
+use crate::trace;
+use crate::{Clock, Error, SystemClock};
+
+use std::error::Error as _;
+use std::time::Instant;
 
 pub type Params<'a> = &'a [&'a (dyn ToSql + Sync)];
 
+const MESSAGE_ID_INDEX: &str = "messages_id";
+const WRONG_EXPECTED_VERSION: &str = "Wrong expected version";
+const STREAM_VERSION: &str = "Stream Version: ";
+
 pub trait Put<T, R> {
     fn put(
         &mut self,
@@ -14,6 +25,11 @@ pub trait Put<T, R> {
         stream_name: &str,
         expected_version: Option<i64>,
     ) -> Result<R, Error>;
+
+    // What `Write` stamps messages with
+    fn clock(&self) -> &dyn Clock {
+        &SystemClock
+    }
 }
 
 impl Put<&MessageData, MessageData> for MessageStore {
@@ -23,7 +39,21 @@ impl Put<&MessageData, MessageData> for MessageStore {
         stream_name: &str,
         expected_version: Option<i64>,
     ) -> Result<MessageData, Error> {
-        put(&mut self.client, data, stream_name, expected_version)
+        let started = Instant::now();
+
+        let written = if self.settings.idempotent_writes {
+            put_idempotent(&mut self.client, data, stream_name, expected_version)
+        } else {
+            put(&mut self.client, data, stream_name, expected_version)
+        }?;
+
+        metrics::write(stream_name, 1, started.elapsed());
+
+        Ok(written)
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
     }
 }
 
@@ -34,7 +64,44 @@ impl Put<Vec<&MessageData>, Vec<MessageData>> for MessageStore {
         stream_name: &str,
         expected_version: Option<i64>,
     ) -> Result<Vec<MessageData>, Error> {
-        put_many(&mut self.client, data, stream_name, expected_version)
+        let started = Instant::now();
+
+        let written = if self.settings.idempotent_writes {
+            put_many_idempotent(&mut self.client, data, stream_name, expected_version)
+        } else {
+            put_many(&mut self.client, data, stream_name, expected_version)
+        }?;
+
+        metrics::write(stream_name, written.len(), started.elapsed());
+
+        Ok(written)
+    }
+
+    fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+}
+
+// The caller commits, so these writes aren't recorded in the written metrics
+impl Put<&MessageData, MessageData> for Transaction<'_> {
+    fn put(
+        &mut self,
+        data: &MessageData,
+        stream_name: &str,
+        expected_version: Option<i64>,
+    ) -> Result<MessageData, Error> {
+        put(self, data, stream_name, expected_version)
+    }
+}
+
+impl Put<Vec<&MessageData>, Vec<MessageData>> for Transaction<'_> {
+    fn put(
+        &mut self,
+        data: Vec<&MessageData>,
+        stream_name: &str,
+        expected_version: Option<i64>,
+    ) -> Result<Vec<MessageData>, Error> {
+        put_many(self, data, stream_name, expected_version)
     }
 }
 
@@ -44,6 +111,41 @@ pub fn put_many<T: GenericClient>(
     stream_name: &str,
     expected_version: Option<i64>,
 ) -> Result<Vec<MessageData>, Error> {
+    put_batch(
+        client,
+        message_data,
+        stream_name,
+        expected_version,
+        |tx, data, stream_name, version| put(tx, data, stream_name, version),
+    )
+}
+
+pub fn put_many_idempotent<T: GenericClient>(
+    client: &mut T,
+    message_data: Vec<&MessageData>,
+    stream_name: &str,
+    expected_version: Option<i64>,
+) -> Result<Vec<MessageData>, Error> {
+    put_batch(
+        client,
+        message_data,
+        stream_name,
+        expected_version,
+        |tx, data, stream_name, version| put_idempotent(tx, data, stream_name, version),
+    )
+}
+
+fn put_batch<T, F>(
+    client: &mut T,
+    message_data: Vec<&MessageData>,
+    stream_name: &str,
+    expected_version: Option<i64>,
+    put: F,
+) -> Result<Vec<MessageData>, Error>
+where
+    T: GenericClient,
+    F: Fn(&mut Transaction, &MessageData, &str, Option<i64>) -> Result<MessageData, Error>,
+{
     let mut tx = client.transaction()?;
     let mut next = expected_version;
     let mut results: Vec<MessageData> = vec![];
@@ -68,12 +170,20 @@ pub fn put<T: GenericClient>(
     stream_name: &str,
     expected_version: Option<i64>,
 ) -> Result<MessageData, Error> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("evt.put", stream_name, expected_version).entered();
+
     let mut message = data.clone();
 
     if message.id.is_none() {
         message.id = Some(identity::uuid());
     }
 
+    #[cfg(feature = "tracing")]
+    if let Some(metadata) = trace::inject(&message.metadata) {
+        message.metadata = metadata;
+    }
+
     let id = message.id.as_ref().unwrap();
 
     let q = "SELECT write_message($1::varchar, $2::varchar, $3::varchar, $4::jsonb, $5::jsonb, $6::bigint);";
@@ -85,15 +195,24 @@ pub fn put<T: GenericClient>(
             &String::from(stream_name),
             &data.message_type,
             &data.data,
-            &data.metadata,
+            &message.metadata,
             &expected_version,
         ],
     );
 
     if let Err(ref e) = row {
-        let msg = e.to_string();
-        if msg.starts_with("ERROR: Wrong expected version") {
-            return Err(Error::ExpectedVersion(msg));
+        if let (Some(expected), Some(actual)) = (expected_version, stream_version(e)) {
+            metrics::conflict(stream_name);
+
+            return Err(Error::ExpectedVersion {
+                stream_name: String::from(stream_name),
+                expected,
+                actual,
+            });
+        }
+
+        if is_duplicate_message(e) {
+            return Err(Error::DuplicateMessage(*id));
         }
     }
 
@@ -103,16 +222,106 @@ pub fn put<T: GenericClient>(
     Ok(message)
 }
 
+// Messages whose id is already in the store are returned as they were written
+// instead of being written again. The write is attempted first, inside a
+// savepoint, so concurrent retries of the same message can't both miss it.
+pub fn put_idempotent<T: GenericClient>(
+    client: &mut T,
+    data: &MessageData,
+    stream_name: &str,
+    expected_version: Option<i64>,
+) -> Result<MessageData, Error> {
+    let mut savepoint = client.transaction()?;
+
+    let error = match put(&mut savepoint, data, stream_name, expected_version) {
+        Ok(written) => {
+            savepoint.commit()?;
+            return Ok(written);
+        }
+        // Retries of a write that succeeded find the stream past the expected
+        // version, rather than the id already written
+        Err(e @ Error::DuplicateMessage(_)) | Err(e @ Error::ExpectedVersion { .. }) => e,
+        Err(e) => return Err(e),
+    };
+
+    savepoint.rollback()?;
+
+    match get_written(client, data)? {
+        Some(written) => {
+            let id = written.id.unwrap_or_default();
+
+            if written.stream_name.as_deref() != Some(stream_name)
+                || written.message_type != data.message_type
+            {
+                return Err(Error::DuplicateMessage(id));
+            }
+
+            Ok(written)
+        }
+        None => Err(error),
+    }
+}
+
+fn get_written<T: GenericClient>(
+    client: &mut T,
+    data: &MessageData,
+) -> Result<Option<MessageData>, Error> {
+    let id = match data.id {
+        Some(ref id) => id,
+        None => return Ok(None),
+    };
+
+    let q = "SELECT stream_name, type, position, global_position FROM messages WHERE id = $1::uuid";
+    let row = client.query_opt(q, &[id])?;
+
+    Ok(row.map(|row| {
+        let mut message = data.clone();
+        message.stream_name = row.get(0);
+        message.message_type = row.get(1);
+        message.position = row.get(2);
+        message.global_position = row.get(3);
+        message
+    }))
+}
+
+fn db_error(error: &postgres::Error) -> Option<&DbError> {
+    error.source().and_then(|e| e.downcast_ref::<DbError>())
+}
+
+fn is_duplicate_message(error: &postgres::Error) -> bool {
+    error.code() == Some(&SqlState::UNIQUE_VIOLATION)
+        && db_error(error).and_then(|e| e.constraint()) == Some(MESSAGE_ID_INDEX)
+}
+
+// Message DB raises
+// "Wrong expected version: 10 (Stream: someStream-123, Stream Version: -1)"
+fn stream_version(error: &postgres::Error) -> Option<i64> {
+    let msg = db_error(error)?.message();
+
+    if !msg.starts_with(WRONG_EXPECTED_VERSION) {
+        return None;
+    }
+
+    msg.rsplit(STREAM_VERSION)
+        .next()?
+        .trim_end_matches(')')
+        .parse()
+        .ok()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::message_store::core::MessageData;
-    use crate::message_store::{controls, INITIAL};
+    use crate::message_store::{controls, Get, INITIAL};
     use crate::stream_name;
     use crate::Error;
     use crate::Json;
     use crate::Uuid;
 
-    use super::Put;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{put, put_idempotent, Put};
 
     #[test]
     fn puts_message_data_into_stream_storage() {
@@ -169,20 +378,163 @@ mod tests {
         let data = controls::new_example();
         let stream_name = stream_name::controls::unique_example();
 
-        let expected = format!(
-            "ERROR: Wrong expected version: 10 (Stream: {}, Stream Version: -1)",
-            stream_name
-        );
-
         let result = store.put(&data, &stream_name, Some(10));
 
-        assert!(result.is_err());
+        match result {
+            Err(Error::ExpectedVersion {
+                stream_name: conflicted,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(stream_name, conflicted);
+                assert_eq!(10, expected);
+                assert_eq!(-1, actual);
+            }
+            _ => panic!("expected version error"),
+        }
+    }
 
-        if let Err(Error::ExpectedVersion(e)) = result {
-            assert_eq!(expected, e);
+    #[test]
+    fn put_results_in_duplicate_message_error_when_id_has_been_written() {
+        let mut store = controls::message_store();
+        let mut data = controls::new_example();
+        let stream_name = stream_name::controls::unique_example();
+        let id = Uuid::new_v4();
+        data.id = Some(id);
+
+        let _: MessageData = store.put(&data, &stream_name, None).unwrap();
+        let result: Result<MessageData, Error> = store.put(&data, &stream_name, None);
+
+        match result {
+            Err(Error::DuplicateMessage(duplicate)) => assert_eq!(id, duplicate),
+            _ => panic!("expected duplicate message error"),
         }
     }
 
+    #[test]
+    fn idempotent_put_treats_a_written_id_as_success() {
+        let mut store = controls::message_store();
+        store.settings.idempotent_writes = true;
+        let mut data = controls::new_example();
+        let stream_name = stream_name::controls::unique_example();
+        data.id = Some(Uuid::new_v4());
+
+        let first: MessageData = store.put(&data, &stream_name, INITIAL).unwrap();
+        let second: MessageData = store.put(&data, &stream_name, INITIAL).unwrap();
+
+        assert_eq!(first.id, second.id);
+        assert_eq!(first.position, second.position);
+        assert!(second.global_position.is_some());
+
+        let count: i64 = store
+            .client
+            .query_one(
+                "SELECT count(*) FROM messages WHERE stream_name = $1",
+                &[&stream_name],
+            )
+            .unwrap()
+            .get(0);
+
+        assert_eq!(1, count);
+    }
+
+    #[test]
+    fn idempotent_put_many_treats_a_written_batch_as_success() {
+        let mut store = controls::message_store();
+        store.settings.idempotent_writes = true;
+        let stream_name = stream_name::controls::unique_example();
+
+        let data: Vec<MessageData> = (0..3)
+            .map(|_| {
+                let mut data = controls::new_example();
+                data.id = Some(Uuid::new_v4());
+                data
+            })
+            .collect();
+
+        let first: Vec<MessageData> = store
+            .put(data.iter().collect(), stream_name.as_str(), INITIAL)
+            .unwrap();
+        let second: Vec<MessageData> = store
+            .put(data.iter().collect(), stream_name.as_str(), INITIAL)
+            .unwrap();
+
+        assert_eq!(3, second.len());
+
+        for (first, second) in first.iter().zip(second.iter()) {
+            assert_eq!(first.id, second.id);
+            assert_eq!(first.position, second.position);
+        }
+    }
+
+    #[test]
+    fn concurrent_idempotent_puts_of_the_same_id_both_succeed() {
+        let stream_name = stream_name::controls::unique_example();
+        let mut data = controls::new_example();
+        data.id = Some(Uuid::new_v4());
+
+        // Hold the first write open so the second one can't see it yet
+        let mut first = controls::message_store();
+        let mut tx = first.client.transaction().unwrap();
+        let written = put_idempotent(&mut tx, &data, &stream_name, None).unwrap();
+
+        let retry = {
+            let stream_name = stream_name.clone();
+            let data = data.clone();
+
+            thread::spawn(move || {
+                let mut store = controls::message_store();
+                store.settings.idempotent_writes = true;
+
+                store.put(&data, &stream_name, None)
+            })
+        };
+
+        thread::sleep(Duration::from_millis(200));
+        tx.commit().unwrap();
+
+        let retried: MessageData = retry.join().unwrap().unwrap();
+
+        assert_eq!(written.id, retried.id);
+        assert_eq!(written.position, retried.position);
+    }
+
+    #[test]
+    fn idempotent_put_of_an_id_written_to_another_stream_fails() {
+        let mut store = controls::message_store();
+        store.settings.idempotent_writes = true;
+        let mut data = controls::new_example();
+        let id = Uuid::new_v4();
+        data.id = Some(id);
+
+        let _: MessageData = store
+            .put(&data, &stream_name::controls::unique_example(), None)
+            .unwrap();
+        let result: Result<MessageData, Error> =
+            store.put(&data, &stream_name::controls::unique_example(), None);
+
+        match result {
+            Err(Error::DuplicateMessage(duplicate)) => assert_eq!(id, duplicate),
+            _ => panic!("expected duplicate message error"),
+        }
+    }
+
+    #[test]
+    fn idempotent_put_within_a_transaction_keeps_the_transaction_usable() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+        let mut data = controls::new_example();
+        data.id = Some(Uuid::new_v4());
+
+        let mut tx = store.client.transaction().unwrap();
+        put_idempotent(&mut tx, &data, &stream_name, None).unwrap();
+        put_idempotent(&mut tx, &data, &stream_name, None).unwrap();
+        put(&mut tx, &controls::new_example(), &stream_name, None).unwrap();
+        tx.commit().unwrap();
+
+        assert_eq!(Some(1), store.stream_version(&stream_name).unwrap());
+    }
+
     #[test]
     fn put_many_will_put_many_data_into_stream_storage() {
         let mut store = controls::message_store();
@@ -0,0 +1,285 @@
+use postgres::Client;
+
+use std::collections::VecDeque;
+
+use crate::message_store::get::get_batch;
+use crate::message_store::{MessageData, MessageStore, Settings};
+use crate::stream_name::is_category;
+use crate::Error;
+
+// Message DB's default batch size
+pub const BATCH_SIZE_DEFAULT: i64 = 1000;
+
+pub trait Read {
+    fn read(&mut self, stream_name: &str) -> Reader<'_>;
+    fn read_from(&mut self, stream_name: &str, position: i64) -> Reader<'_>;
+}
+
+impl Read for MessageStore {
+    fn read(&mut self, stream_name: &str) -> Reader<'_> {
+        Reader::new(&mut self.client, &self.settings, stream_name, None)
+    }
+
+    fn read_from(&mut self, stream_name: &str, position: i64) -> Reader<'_> {
+        Reader::new(
+            &mut self.client,
+            &self.settings,
+            stream_name,
+            Some(position),
+        )
+    }
+}
+
+pub struct Reader<'a> {
+    client: &'a mut Client,
+    settings: &'a Settings,
+    stream_name: String,
+    category: bool,
+    position: i64,
+    // After the last row fetched, which is past the last message in the
+    // batch when malformed rows at the end of it were skipped
+    fetched_position: i64,
+    batch: VecDeque<MessageData>,
+    done: bool,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(
+        client: &'a mut Client,
+        settings: &'a Settings,
+        stream_name: &str,
+        position: Option<i64>,
+    ) -> Self {
+        let category = is_category(stream_name);
+
+        // Streams start at position 0, categories at global position 1
+        let position = position.unwrap_or(if category { 1 } else { 0 });
+
+        Self {
+            client,
+            settings,
+            stream_name: String::from(stream_name),
+            category,
+            position,
+            fetched_position: position,
+            batch: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    fn batch_size(&self) -> i64 {
+        self.settings.batch_size.unwrap_or(BATCH_SIZE_DEFAULT)
+    }
+
+    fn fetch(&mut self) -> Result<(), Error> {
+        let batch = get_batch(
+            self.client,
+            self.settings,
+            &self.stream_name,
+            Some(self.fetched_position),
+        )?;
+
+        // Skipped rows count, so a batch of them isn't taken for the end
+        if (batch.rows as i64) < self.batch_size() {
+            self.done = true;
+        }
+
+        let last = if self.category {
+            batch.last_global_position
+        } else {
+            batch.last_position
+        };
+
+        if let Some(last) = last {
+            self.fetched_position = last + 1;
+        }
+
+        self.batch.extend(batch.messages);
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<MessageData, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // A batch of nothing but skipped rows is followed by the next one
+        while self.batch.is_empty() && !self.done {
+            if let Err(e) = self.fetch() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        let message = match self.batch.pop_front() {
+            Some(message) => message,
+            None => {
+                self.position = self.fetched_position;
+                return None;
+            }
+        };
+
+        let current = if self.category {
+            message.global_position
+        } else {
+            message.position
+        };
+
+        if let Some(current) = current {
+            self.position = current + 1;
+        }
+
+        if self.batch.is_empty() {
+            self.position = self.fetched_position;
+        }
+
+        Some(Ok(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use postgres::Client;
+
+    use crate::message_store::{controls, MessageData, Put, Read, Settings};
+    use crate::{db, identity, stream_name};
+
+    use super::Reader;
+
+    // Message DB reads from whichever messages table is first on the search
+    // path, so a temporary one can hold rows it could never have written
+    fn client_with_messages(stream_name: &str, malformed: &[bool]) -> Client {
+        let mut client = db::build();
+
+        client
+            .batch_execute(
+                "CREATE TEMP TABLE messages (
+                     id varchar, stream_name varchar, type varchar, position bigint,
+                     global_position bigint, data varchar, metadata varchar, time timestamp
+                 )",
+            )
+            .unwrap();
+
+        for (position, malformed) in malformed.iter().enumerate() {
+            let position = position as i64;
+            let data = if *malformed { "not json" } else { "{}" };
+
+            client
+                .execute(
+                    "INSERT INTO messages VALUES ($1, $2, 'SomeType', $3, $4, $5, '{}', now())",
+                    &[
+                        &identity::uuid().to_string(),
+                        &stream_name,
+                        &position,
+                        &(position + 1),
+                        &data,
+                    ],
+                )
+                .unwrap();
+        }
+
+        client
+    }
+
+    #[test]
+    fn reads_a_stream_across_batches() {
+        let mut store = controls::message_store();
+        store.settings.batch_size = Some(2);
+        let stream_name = stream_name::controls::unique_example();
+        let data: Vec<MessageData> = (0..5).map(|_| controls::new_example()).collect();
+
+        let stored: Vec<MessageData> = store
+            .put(data.iter().collect(), &stream_name, None)
+            .unwrap();
+
+        let read: Vec<MessageData> = store.read(&stream_name).collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(5, read.len());
+
+        for (stored, read) in stored.iter().zip(read.iter()) {
+            assert_eq!(stored.id, read.id);
+            assert_eq!(stored.position, read.position);
+        }
+    }
+
+    #[test]
+    fn reads_a_category_across_batches() {
+        let mut store = controls::message_store();
+        store.settings.batch_size = Some(2);
+        let category = stream_name::controls::unique_category();
+
+        for _ in 0..3 {
+            let stream_name = stream_name!(&category, id = identity::uuid());
+            let _: MessageData = store
+                .put(&controls::new_example(), &stream_name, None)
+                .unwrap();
+        }
+
+        let read: Vec<MessageData> = store.read(&category).collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(3, read.len());
+        assert!(read[0].global_position < read[1].global_position);
+        assert!(read[1].global_position < read[2].global_position);
+    }
+
+    #[test]
+    fn reads_a_stream_from_a_position() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+        let data: Vec<MessageData> = (0..3).map(|_| controls::new_example()).collect();
+
+        let _: Vec<MessageData> = store
+            .put(data.iter().collect(), &stream_name, None)
+            .unwrap();
+
+        let read: Vec<MessageData> = store
+            .read_from(&stream_name, 1)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(2, read.len());
+        assert_eq!(Some(1), read[0].position);
+    }
+
+    #[test]
+    fn reads_past_batches_of_skipped_messages() {
+        let stream_name = stream_name::controls::unique_example();
+        let mut client =
+            client_with_messages(&stream_name, &[false, true, true, true, false, true]);
+        let settings = Settings {
+            batch_size: Some(2),
+            skip_malformed: true,
+            ..Default::default()
+        };
+
+        let mut reader = Reader::new(&mut client, &settings, &stream_name, None);
+        let read: Vec<MessageData> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+
+        let positions: Vec<Option<i64>> = read.iter().map(|message| message.position).collect();
+
+        assert_eq!(vec![Some(0), Some(4)], positions);
+        assert_eq!(6, reader.position());
+    }
+
+    #[test]
+    fn moves_past_skipped_messages_at_the_end_of_a_category() {
+        let category = stream_name::controls::unique_category();
+        let stream_name = stream_name!(&category, id = identity::uuid());
+        let mut client = client_with_messages(&stream_name, &[false, true]);
+        let settings = Settings {
+            skip_malformed: true,
+            ..Default::default()
+        };
+
+        let mut reader = Reader::new(&mut client, &settings, &category, None);
+        let read: Vec<MessageData> = reader.by_ref().collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(1, read.len());
+        assert_eq!(3, reader.position());
+    }
+}
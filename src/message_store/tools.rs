@@ -1,3 +1,5 @@
+pub mod ndjson;
+
 use std::io::Write;
 
 use crate::message_store::MessageData;
@@ -11,9 +13,9 @@ pub fn bulk_insert(client: &mut postgres::Client, data: Vec<&MessageData>) -> Re
     for message in data.iter() {
         writer.write_fmt(format_args!(
             "{}\t{}\t{}\t{}\t{}\n",
-            option(&message.stream_name)?,
+            option(&message.stream_name, "stream_name")?,
             message.message_type,
-            option(&message.position)?,
+            option(&message.position, "position")?,
             message.data,
             message.metadata,
         ))?;
@@ -24,10 +26,10 @@ pub fn bulk_insert(client: &mut postgres::Client, data: Vec<&MessageData>) -> Re
     Ok(data.len())
 }
 
-fn option<T>(field: &Option<T>) -> Result<&T, Error> {
+fn option<'a, T>(field: &'a Option<T>, name: &'static str) -> Result<&'a T, Error> {
     match field {
         Some(value) => Ok(value),
-        None => Err(Error::MissingField),
+        None => Err(Error::MissingField(name)),
     }
 }
 
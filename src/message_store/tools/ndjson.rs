@@ -0,0 +1,307 @@
+use std::io::{BufRead, Write};
+
+use postgres::Client;
+
+use crate::message_store::get::get_messages;
+use crate::message_store::{MessageData, Reader, Settings};
+use crate::{Error, Json};
+
+use super::option;
+
+const BATCH_SIZE: i64 = 1000;
+
+pub fn export<W: Write>(
+    client: &mut Client,
+    stream_name: &str,
+    writer: &mut W,
+) -> Result<usize, Error> {
+    let settings = Settings {
+        batch_size: Some(BATCH_SIZE),
+        ..Default::default()
+    };
+    let mut count = 0;
+
+    for message in Reader::new(client, &settings, stream_name, None) {
+        write_line(writer, &message?)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+pub fn export_range<W: Write>(
+    client: &mut Client,
+    from: i64,
+    to: Option<i64>,
+    writer: &mut W,
+) -> Result<usize, Error> {
+    let q = "SELECT id::varchar, stream_name::varchar, type::varchar, \
+                    position, global_position, data::varchar, metadata::varchar, time \
+             FROM messages \
+             WHERE global_position >= $1 \
+               AND ($2::bigint IS NULL OR global_position <= $2) \
+             ORDER BY global_position \
+             LIMIT $3";
+    let mut position = from;
+    let mut count = 0;
+
+    loop {
+        let messages = get_messages(client, q, &[&position, &to, &BATCH_SIZE])?;
+
+        for message in messages.iter() {
+            write_line(writer, message)?;
+            position = option(&message.global_position, "global_position")? + 1;
+        }
+
+        count += messages.len();
+
+        if messages.len() < BATCH_SIZE as usize {
+            break;
+        }
+    }
+
+    Ok(count)
+}
+
+// Keeps ids, positions and times. Moving the global position sequence past the
+// imported messages requires UPDATE on messages_global_position_seq
+pub fn import<R: BufRead>(client: &mut Client, reader: R) -> Result<usize, Error> {
+    let q =
+        "COPY messages(id, stream_name, type, position, global_position, data, metadata, time) \
+             FROM stdin";
+    let mut tx = client.transaction()?;
+    let mut writer = tx.copy_in(q)?;
+    let mut count = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let message: MessageData = serde_json::from_str(&line)?;
+
+        writer.write_fmt(format_args!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            option(&message.id, "id")?,
+            escape(option(&message.stream_name, "stream_name")?),
+            escape(&message.message_type),
+            option(&message.position, "position")?,
+            option(&message.global_position, "global_position")?,
+            json(&message.data),
+            json(&message.metadata),
+            option(&message.time, "time")?.naive_utc(),
+        ))?;
+
+        count += 1;
+    }
+
+    writer.finish()?;
+
+    tx.execute(
+        "SELECT setval('messages_global_position_seq', m.global_position) \
+         FROM (SELECT max(global_position) AS global_position FROM messages) m, \
+              messages_global_position_seq s \
+         WHERE m.global_position > s.last_value",
+        &[],
+    )?;
+
+    tx.commit()?;
+
+    Ok(count)
+}
+
+fn write_line<W: Write>(writer: &mut W, message: &MessageData) -> Result<(), Error> {
+    serde_json::to_writer(&mut *writer, message)?;
+    writer.write_all(b"\n")?;
+
+    Ok(())
+}
+
+// Data and metadata read as null were SQL NULL, which COPY writes as \N
+fn json(value: &Json) -> String {
+    if value.is_null() {
+        String::from("\\N")
+    } else {
+        escape(&value.to_string())
+    }
+}
+
+// COPY text format treats backslashes, tabs and newlines as control characters
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message_store::{controls, Get, MessageData, Put};
+    use crate::{db, identity, stream_name};
+
+    use super::*;
+
+    #[test]
+    fn exports_a_stream_as_ndjson() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+        let data: Vec<MessageData> = (0..3).map(|_| controls::new_example()).collect();
+
+        let stored: Vec<MessageData> = store
+            .put(data.iter().collect(), &stream_name, None)
+            .unwrap();
+
+        let mut output: Vec<u8> = vec![];
+        let count = export(&mut store.client, &stream_name, &mut output).unwrap();
+
+        let exported: Vec<MessageData> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(3, count);
+        assert_eq!(3, exported.len());
+
+        for (stored, exported) in stored.iter().zip(exported.iter()) {
+            assert_eq!(stored.id, exported.id);
+            assert_eq!(stored.position, exported.position);
+            assert!(exported.global_position.is_some());
+            assert!(exported.time.is_some());
+        }
+    }
+
+    #[test]
+    fn exports_a_global_position_range() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+        let data: Vec<MessageData> = (0..3).map(|_| controls::new_example()).collect();
+
+        let _: Vec<MessageData> = store
+            .put(data.iter().collect(), &stream_name, None)
+            .unwrap();
+
+        let stored = store.get(&stream_name, None).unwrap();
+        let from = stored[0].global_position.unwrap();
+        let to = stored[1].global_position.unwrap();
+
+        let mut output: Vec<u8> = vec![];
+        export_range(&mut store.client, from, Some(to), &mut output).unwrap();
+
+        // Other tests may write to the store in the meantime
+        let exported: Vec<MessageData> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<MessageData>(line).unwrap())
+            .filter(|message| message.stream_name.as_deref() == Some(stream_name.as_str()))
+            .collect();
+
+        assert_eq!(2, exported.len());
+
+        for (stored, exported) in stored.iter().zip(exported.iter()) {
+            assert_eq!(stored.id, exported.id);
+            assert_eq!(stored.position, exported.position);
+            assert_eq!(stored.global_position, exported.global_position);
+        }
+    }
+
+    #[test]
+    fn imports_null_data_and_metadata_as_null() {
+        let mut client = db::build();
+        let source = stream_name::controls::unique_example();
+        let target = stream_name::controls::unique_example();
+
+        client
+            .query_one(
+                "SELECT write_message($1::varchar, $2::varchar, 'SomeType', NULL, NULL)",
+                &[&identity::uuid().to_string(), &source],
+            )
+            .unwrap();
+
+        let mut output: Vec<u8> = vec![];
+        export(&mut client, &source, &mut output).unwrap();
+
+        let mut message: MessageData = serde_json::from_slice(&output).unwrap();
+        let row = client
+            .query_one("SELECT nextval('messages_global_position_seq')", &[])
+            .unwrap();
+        message.id = Some(identity::uuid());
+        message.stream_name = Some(target.clone());
+        message.global_position = row.get(0);
+
+        let mut input: Vec<u8> = vec![];
+        write_line(&mut input, &message).unwrap();
+
+        import(&mut client, input.as_slice()).unwrap();
+
+        let row = client
+            .query_one(
+                "SELECT data IS NULL, metadata IS NULL FROM messages WHERE stream_name = $1",
+                &[&target],
+            )
+            .unwrap();
+
+        assert!(row.get::<_, bool>(0));
+        assert!(row.get::<_, bool>(1));
+    }
+
+    #[test]
+    fn imports_ndjson_preserving_identity_and_positions() {
+        let mut store = controls::message_store();
+        let source = stream_name::controls::unique_example();
+        let category = stream_name::controls::unique_category();
+        let data: Vec<MessageData> = (0..3).map(|_| controls::new_example()).collect();
+
+        let _: Vec<MessageData> = store.put(data.iter().collect(), &source, None).unwrap();
+
+        let mut output: Vec<u8> = vec![];
+        export(&mut store.client, &source, &mut output).unwrap();
+
+        // Re-key the export so it can be imported alongside the original
+        let mut client = db::build();
+        let target = stream_name!(&category, id = identity::uuid());
+        let messages: Vec<MessageData> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let mut message: MessageData = serde_json::from_str(line).unwrap();
+                let row = client
+                    .query_one("SELECT nextval('messages_global_position_seq')", &[])
+                    .unwrap();
+                message.id = Some(identity::uuid());
+                message.stream_name = Some(target.clone());
+                message.global_position = row.get(0);
+                message
+            })
+            .collect();
+
+        let mut input: Vec<u8> = vec![];
+        for message in messages.iter() {
+            write_line(&mut input, message).unwrap();
+        }
+
+        let count = import(&mut client, input.as_slice()).unwrap();
+
+        let imported = store.get(&target, None).unwrap();
+
+        assert_eq!(3, count);
+        assert_eq!(3, imported.len());
+
+        for (expected, imported) in messages.iter().zip(imported.iter()) {
+            assert_eq!(expected.id, imported.id);
+            assert_eq!(expected.position, imported.position);
+            assert_eq!(expected.global_position, imported.global_position);
+            assert_eq!(expected.time, imported.time);
+            assert_eq!(expected.data, imported.data);
+            assert_eq!(expected.metadata, imported.metadata);
+        }
+    }
+
+    #[test]
+    fn escapes_copy_control_characters() {
+        assert_eq!("a\\\\b\\tc\\nd", escape("a\\b\tc\nd"));
+    }
+}
@@ -0,0 +1,147 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::message_store::put::{put_many, put_many_idempotent};
+use crate::message_store::{MessageData, MessageStore};
+use crate::messaging::write::stamp;
+use crate::messaging::Message;
+use crate::{metrics, Error};
+
+use std::time::Instant;
+
+struct StreamWrite {
+    stream_name: String,
+    messages: Vec<MessageData>,
+    expected_version: Option<i64>,
+    // Written as messages, so stamped with the store's clock
+    stamp: bool,
+}
+
+// Collects writes to any number of streams and commits them in a single
+// transaction, so either every write succeeds or none of them are stored
+#[derive(Default)]
+pub struct UnitOfWork {
+    writes: Vec<StreamWrite>,
+}
+
+impl UnitOfWork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, data: &MessageData, stream_name: &str, expected_version: Option<i64>) {
+        self.put_many(vec![data], stream_name, expected_version);
+    }
+
+    pub fn put_many(
+        &mut self,
+        data: Vec<&MessageData>,
+        stream_name: &str,
+        expected_version: Option<i64>,
+    ) {
+        self.writes.push(StreamWrite {
+            stream_name: String::from(stream_name),
+            messages: data.into_iter().cloned().collect(),
+            expected_version,
+            stamp: false,
+        });
+    }
+
+    pub fn write<T>(
+        &mut self,
+        message: &Message<T>,
+        stream_name: &str,
+        expected_version: Option<i64>,
+    ) where
+        T: Serialize + DeserializeOwned + Default,
+    {
+        self.writes.push(StreamWrite {
+            stream_name: String::from(stream_name),
+            messages: vec![message.as_message_data()],
+            expected_version,
+            stamp: true,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn commit(mut self, store: &mut MessageStore) -> Result<Vec<MessageData>, Error> {
+        for write in self.writes.iter_mut().filter(|write| write.stamp) {
+            for message in write.messages.iter_mut() {
+                stamp(message, store.clock.as_ref())?;
+            }
+        }
+
+        let started = Instant::now();
+        let idempotent = store.settings.idempotent_writes;
+        let mut tx = store.client.transaction()?;
+        let mut results: Vec<MessageData> = vec![];
+
+        for write in self.writes.iter() {
+            let data = write.messages.iter().collect();
+            let stream_name = write.stream_name.as_str();
+
+            let written = if idempotent {
+                put_many_idempotent(&mut tx, data, stream_name, write.expected_version)?
+            } else {
+                put_many(&mut tx, data, stream_name, write.expected_version)?
+            };
+
+            results.extend(written);
+        }
+
+        tx.commit()?;
+
+        for write in self.writes.iter() {
+            metrics::write(&write.stream_name, write.messages.len(), started.elapsed());
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message_store::{controls, Get, INITIAL};
+    use crate::{stream_name, Error};
+
+    use super::UnitOfWork;
+
+    #[test]
+    fn commits_writes_to_several_streams() {
+        let mut store = controls::message_store();
+        let entity_stream = stream_name::controls::unique_example();
+        let command_stream = stream_name::controls::unique_example();
+
+        let mut unit_of_work = UnitOfWork::new();
+        unit_of_work.put(&controls::new_example(), &entity_stream, INITIAL);
+        unit_of_work.put(&controls::new_example(), &command_stream, None);
+
+        let written = unit_of_work.commit(&mut store).unwrap();
+
+        assert_eq!(2, written.len());
+        assert_eq!(Some(entity_stream.clone()), written[0].stream_name);
+        assert_eq!(Some(command_stream.clone()), written[1].stream_name);
+        assert_eq!(Some(0), store.stream_version(&entity_stream).unwrap());
+        assert_eq!(Some(0), store.stream_version(&command_stream).unwrap());
+    }
+
+    #[test]
+    fn writes_nothing_when_any_stream_is_not_at_its_expected_version() {
+        let mut store = controls::message_store();
+        let entity_stream = stream_name::controls::unique_example();
+        let command_stream = stream_name::controls::unique_example();
+
+        let mut unit_of_work = UnitOfWork::new();
+        unit_of_work.put(&controls::new_example(), &entity_stream, INITIAL);
+        unit_of_work.put(&controls::new_example(), &command_stream, Some(10));
+
+        let result = unit_of_work.commit(&mut store);
+
+        assert!(matches!(result, Err(Error::ExpectedVersion { .. })));
+        assert!(!store.stream_exists(&entity_stream).unwrap());
+        assert!(!store.stream_exists(&command_stream).unwrap());
+    }
+}
@@ -121,7 +121,10 @@ where
 
             Ok(Message(val, id, metadata))
         } else {
-            Err(Error::MessageType)
+            Err(Error::MessageType {
+                expected: T::message_type(),
+                found: value.message_type,
+            })
         }
     }
 }
@@ -22,7 +22,12 @@ pub struct Metadata {
     pub causation_message_position: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub causation_message_global_position: Option<i64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    // Message DB filters categories by correlation on the camel case key
+    #[serde(
+        rename = "correlationStreamName",
+        alias = "correlation_stream_name",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub correlation_stream_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reply_stream_name: Option<String>,
@@ -40,6 +45,7 @@ impl Metadata {
             causation_message_stream_name: other.causation_message_stream_name.clone(),
             causation_message_position: other.causation_message_position,
             causation_message_global_position: other.causation_message_global_position,
+            correlation_stream_name: other.correlation_stream_name.clone(),
             reply_stream_name: other.reply_stream_name.clone(),
             trace_info: other.trace_info.clone(),
             ..Default::default()
@@ -50,6 +56,7 @@ impl Metadata {
         self.causation_message_stream_name == other.causation_message_stream_name
             && self.causation_message_position == other.causation_message_position
             && self.causation_message_global_position == other.causation_message_global_position
+            && self.correlation_stream_name == other.correlation_stream_name
             && self.reply_stream_name == other.reply_stream_name
     }
 
@@ -136,6 +143,7 @@ mod tests {
         let causation_message_stream_name = other.causation_message_stream_name;
         let causation_message_position = other.causation_message_position;
         let causation_message_global_position = other.causation_message_global_position;
+        let correlation_stream_name = other.correlation_stream_name;
         let reply_stream_name = other.reply_stream_name;
         let trace_info = other.trace_info;
 
@@ -151,10 +159,36 @@ mod tests {
             metadata.causation_message_global_position,
             causation_message_global_position
         );
+        assert_eq!(metadata.correlation_stream_name, correlation_stream_name);
         assert_eq!(metadata.reply_stream_name, reply_stream_name);
         assert_eq!(metadata.trace_info, trace_info);
     }
 
+    #[test]
+    fn serializes_correlation_stream_name_for_message_db() {
+        let metadata = controls::metadata::example();
+
+        let json = serde_json::to_value(&metadata).unwrap();
+
+        assert_eq!(
+            Some(controls::metadata::correlation_stream_name().as_str()),
+            json["correlationStreamName"].as_str()
+        );
+        assert!(json.get("correlation_stream_name").is_none());
+    }
+
+    #[test]
+    fn deserializes_snake_case_correlation_stream_name() {
+        let json = serde_json::json!({ "correlation_stream_name": "someStream" });
+
+        let metadata: Metadata = serde_json::from_value(json).unwrap();
+
+        assert_eq!(
+            Some(String::from("someStream")),
+            metadata.correlation_stream_name
+        );
+    }
+
     #[test]
     fn following_copies_traces() {
         let mut other = controls::metadata::example();
@@ -1,9 +1,12 @@
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use serde_json::Value;
 
 use crate::message_store::{MessageData, Put, INITIAL};
 use crate::messaging::Message;
-use crate::{Error, MessageStore};
+use crate::{Clock, Error};
+
+const TIME: &str = "time";
 
 pub trait Write<T, D, R>: Put<D, R> {
     fn write(
@@ -11,51 +14,156 @@ pub trait Write<T, D, R>: Put<D, R> {
         batch: T,
         stream_name: &str,
         expected_version: Option<i64>,
-    ) -> Result<(), Error>;
+    ) -> Result<R, Error>;
 
-    fn write_initial(&mut self, batch: T, stream_name: &str) -> Result<(), Error>;
+    fn write_initial(&mut self, batch: T, stream_name: &str) -> Result<R, Error>;
 }
 
-impl<T> Write<&Message<T>, &MessageData, MessageData> for MessageStore
+impl<T, S> Write<&Message<T>, &MessageData, MessageData> for S
 where
     T: Serialize + DeserializeOwned + Default,
+    S: for<'a> Put<&'a MessageData, MessageData>,
 {
     fn write(
         &mut self,
         batch: &Message<T>,
         stream_name: &str,
         expected_version: Option<i64>,
-    ) -> Result<(), Error> {
-        let data = batch.as_message_data();
-        self.put(&data, stream_name, expected_version)?;
+    ) -> Result<MessageData, Error> {
+        let mut data = batch.as_message_data();
+        stamp(&mut data, self.clock())?;
 
-        Ok(())
+        self.put(&data, stream_name, expected_version)
     }
 
-    fn write_initial(&mut self, batch: &Message<T>, stream_name: &str) -> Result<(), Error> {
+    fn write_initial(
+        &mut self,
+        batch: &Message<T>,
+        stream_name: &str,
+    ) -> Result<MessageData, Error> {
         self.write(batch, stream_name, INITIAL)
     }
 }
 
-impl<T> Write<Vec<&Message<T>>, Vec<&MessageData>, Vec<MessageData>> for MessageStore
+impl<T, S> Write<Vec<&Message<T>>, Vec<&MessageData>, Vec<MessageData>> for S
 where
     T: Serialize + DeserializeOwned + Default,
+    S: for<'a> Put<Vec<&'a MessageData>, Vec<MessageData>>,
 {
     fn write(
         &mut self,
         batch: Vec<&Message<T>>,
         stream_name: &str,
         expected_version: Option<i64>,
-    ) -> Result<(), Error> {
-        let data: Vec<MessageData> = batch.into_iter().map(|msg| msg.as_message_data()).collect();
-        let refs: Vec<&MessageData> = data.iter().collect();
+    ) -> Result<Vec<MessageData>, Error> {
+        let mut data: Vec<MessageData> =
+            batch.into_iter().map(|msg| msg.as_message_data()).collect();
 
-        self.put(refs, stream_name, expected_version)?;
+        for message in data.iter_mut() {
+            stamp(message, self.clock())?;
+        }
 
-        Ok(())
+        let refs: Vec<&MessageData> = data.iter().collect();
+
+        self.put(refs, stream_name, expected_version)
     }
 
-    fn write_initial(&mut self, batch: Vec<&Message<T>>, stream_name: &str) -> Result<(), Error> {
+    fn write_initial(
+        &mut self,
+        batch: Vec<&Message<T>>,
+        stream_name: &str,
+    ) -> Result<Vec<MessageData>, Error> {
         self.write(batch, stream_name, INITIAL)
     }
 }
+
+// Sets the metadata time of messages that don't have one to the clock's time
+pub(crate) fn stamp(message: &mut MessageData, clock: &dyn Clock) -> Result<(), Error> {
+    if let Some(metadata) = message.metadata.as_object_mut() {
+        if matches!(metadata.get(TIME), None | Some(Value::Null)) {
+            metadata.insert(String::from(TIME), serde_json::to_value(clock.now())?);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message_store::{controls, Get, MessageData, MessageStore};
+    use crate::messaging::controls::{message, metadata};
+    use crate::messaging::{Metadata, Write};
+    use crate::{clock, stream_name, FixedClock};
+
+    fn written_time(
+        store: &mut MessageStore,
+        stream_name: &str,
+    ) -> Option<crate::DateTime<crate::Utc>> {
+        let written = store.get_last(stream_name).unwrap().unwrap();
+        let metadata: Metadata = serde_json::from_value(written.metadata).unwrap();
+
+        metadata.time
+    }
+
+    #[test]
+    fn stamps_messages_without_a_time_with_the_stores_clock() {
+        let mut store =
+            controls::message_store().with_clock(FixedClock::new(clock::controls::time()));
+        let stream_name = stream_name::controls::unique_example();
+
+        let _: MessageData = store
+            .write(&message::command(), &stream_name, None)
+            .unwrap();
+
+        assert_eq!(
+            Some(clock::controls::time()),
+            written_time(&mut store, &stream_name)
+        );
+    }
+
+    #[test]
+    fn keeps_the_time_messages_already_have() {
+        let mut store =
+            controls::message_store().with_clock(FixedClock::new(clock::controls::time()));
+        let stream_name = stream_name::controls::unique_example();
+
+        let _: MessageData = store
+            .write(&message::example(), &stream_name, None)
+            .unwrap();
+
+        assert_eq!(
+            Some(metadata::time()),
+            written_time(&mut store, &stream_name)
+        );
+    }
+
+    #[test]
+    fn writes_within_a_callers_transaction() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+        let message = message::example();
+
+        let mut tx = store.client.transaction().unwrap();
+        let written: MessageData = tx.write_initial(&message, &stream_name).unwrap();
+        tx.commit().unwrap();
+
+        assert_eq!(Some(0), written.position);
+        assert_eq!(Some(0), store.stream_version(&stream_name).unwrap());
+    }
+
+    #[test]
+    fn writes_are_discarded_when_the_callers_transaction_rolls_back() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+        let messages = [message::example(), message::example()];
+
+        let mut tx = store.client.transaction().unwrap();
+        let written: Vec<MessageData> = tx
+            .write(messages.iter().collect(), &stream_name, None)
+            .unwrap();
+        tx.rollback().unwrap();
+
+        assert_eq!(2, written.len());
+        assert!(!store.stream_exists(&stream_name).unwrap());
+    }
+}
@@ -0,0 +1,227 @@
+// Records what the store and consumers are doing through the `metrics` facade
+// when the `metrics` feature is enabled, so any exporter can be installed by
+// the application. Without the feature every function here does nothing.
+pub(crate) const ENABLED: bool = cfg!(feature = "metrics");
+
+pub(crate) use self::recorder::*;
+
+#[cfg(feature = "metrics")]
+mod recorder {
+    use std::time::Duration;
+
+    use crate::stream_name::get_category;
+
+    pub const MESSAGES_WRITTEN: &str = "evt_messages_written_total";
+    pub const WRITE_DURATION: &str = "evt_write_duration_seconds";
+    pub const MESSAGES_READ: &str = "evt_messages_read_total";
+    pub const READ_DURATION: &str = "evt_read_duration_seconds";
+    pub const EXPECTED_VERSION_CONFLICTS: &str = "evt_expected_version_conflicts_total";
+    pub const CONSUMER_LAG: &str = "evt_consumer_lag";
+    pub const MESSAGES_HANDLED: &str = "evt_messages_handled_total";
+    pub const ENTITY_CACHE_HITS: &str = "evt_entity_cache_hits_total";
+    pub const ENTITY_CACHE_MISSES: &str = "evt_entity_cache_misses_total";
+    pub const MALFORMED_MESSAGES: &str = "evt_malformed_messages_total";
+
+    // Only committed writes are recorded, so writes that are rolled back, or
+    // made in a caller's transaction the store can't see committed, aren't
+    pub fn write(stream_name: &str, messages: usize, elapsed: Duration) {
+        let category = get_category(stream_name);
+
+        ::metrics::counter!(MESSAGES_WRITTEN, "category" => category.clone())
+            .increment(messages as u64);
+        ::metrics::histogram!(WRITE_DURATION, "category" => category).record(elapsed);
+    }
+
+    pub fn read(stream_name: &str, messages: usize, elapsed: Duration) {
+        let category = get_category(stream_name);
+
+        ::metrics::counter!(MESSAGES_READ, "category" => category.clone())
+            .increment(messages as u64);
+        ::metrics::histogram!(READ_DURATION, "category" => category).record(elapsed);
+    }
+
+    pub fn conflict(stream_name: &str) {
+        let category = get_category(stream_name);
+
+        ::metrics::counter!(EXPECTED_VERSION_CONFLICTS, "category" => category).increment(1);
+    }
+
+    pub fn consumer_lag(category: &str, identifier: Option<&str>, lag: i64) {
+        ::metrics::gauge!(
+            CONSUMER_LAG,
+            "category" => category.to_string(),
+            "identifier" => identifier.unwrap_or_default().to_string()
+        )
+        .set(lag as f64);
+    }
+
+    pub fn handled(category: &str, message_type: &str) {
+        ::metrics::counter!(
+            MESSAGES_HANDLED,
+            "category" => category.to_string(),
+            "message_type" => message_type.to_string()
+        )
+        .increment(1);
+    }
+
+    pub fn entity_cache(category: &str, hit: bool) {
+        let name = if hit {
+            ENTITY_CACHE_HITS
+        } else {
+            ENTITY_CACHE_MISSES
+        };
+
+        ::metrics::counter!(name, "category" => category.to_string()).increment(1);
+    }
+
+    pub fn malformed(stream_name: &str) {
+        let category = get_category(stream_name);
+
+        ::metrics::counter!(MALFORMED_MESSAGES, "category" => category).increment(1);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod recorder {
+    use std::time::Duration;
+
+    pub fn write(_: &str, _: usize, _: Duration) {}
+    pub fn read(_: &str, _: usize, _: Duration) {}
+    pub fn conflict(_: &str) {}
+    pub fn consumer_lag(_: &str, _: Option<&str>, _: i64) {}
+    pub fn handled(_: &str, _: &str) {}
+    pub fn entity_cache(_: &str, _: bool) {}
+    pub fn malformed(_: &str) {}
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use ::metrics::{
+        Counter, CounterFn, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+
+    use crate::message_store::{controls, Get, MessageData, Put, UnitOfWork, INITIAL};
+    use crate::stream_name;
+
+    use super::*;
+
+    type Totals = Arc<Mutex<HashMap<String, u64>>>;
+
+    #[derive(Default)]
+    struct CountingRecorder {
+        totals: Totals,
+    }
+
+    struct Total {
+        name: String,
+        totals: Totals,
+    }
+
+    impl CounterFn for Total {
+        fn increment(&self, value: u64) {
+            *self
+                .totals
+                .lock()
+                .unwrap()
+                .entry(self.name.clone())
+                .or_default() += value;
+        }
+
+        fn absolute(&self, value: u64) {
+            self.totals.lock().unwrap().insert(self.name.clone(), value);
+        }
+    }
+
+    impl Recorder for CountingRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::from_arc(Arc::new(Total {
+                name: key.name().to_string(),
+                totals: self.totals.clone(),
+            }))
+        }
+
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    impl CountingRecorder {
+        fn total(&self, name: &str) -> u64 {
+            *self.totals.lock().unwrap().get(name).unwrap_or(&0)
+        }
+    }
+
+    #[test]
+    fn counts_messages_written_and_read() {
+        let recorder = CountingRecorder::default();
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+
+        ::metrics::with_local_recorder(&recorder, || {
+            let _: Vec<MessageData> = store
+                .put(
+                    vec![&controls::new_example(), &controls::new_example()],
+                    &stream_name,
+                    None,
+                )
+                .unwrap();
+
+            store.get(&stream_name, None).unwrap();
+        });
+
+        assert_eq!(2, recorder.total(MESSAGES_WRITTEN));
+        assert_eq!(2, recorder.total(MESSAGES_READ));
+    }
+
+    #[test]
+    fn counts_expected_version_conflicts() {
+        let recorder = CountingRecorder::default();
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+
+        ::metrics::with_local_recorder(&recorder, || {
+            let result: Result<MessageData, _> =
+                store.put(&controls::new_example(), &stream_name, Some(5));
+
+            assert!(result.is_err());
+        });
+
+        assert_eq!(1, recorder.total(EXPECTED_VERSION_CONFLICTS));
+    }
+
+    #[test]
+    fn counts_written_messages_once_committed() {
+        let recorder = CountingRecorder::default();
+        let mut store = controls::message_store();
+        let entity_stream = stream_name::controls::unique_example();
+        let command_stream = stream_name::controls::unique_example();
+
+        ::metrics::with_local_recorder(&recorder, || {
+            let mut rolled_back = UnitOfWork::new();
+            rolled_back.put(&controls::new_example(), &entity_stream, INITIAL);
+            rolled_back.put(&controls::new_example(), &command_stream, Some(10));
+
+            assert!(rolled_back.commit(&mut store).is_err());
+            assert_eq!(0, recorder.total(MESSAGES_WRITTEN));
+
+            let mut committed = UnitOfWork::new();
+            committed.put(&controls::new_example(), &entity_stream, INITIAL);
+            committed.put(&controls::new_example(), &command_stream, None);
+
+            committed.commit(&mut store).unwrap();
+        });
+
+        assert_eq!(2, recorder.total(MESSAGES_WRITTEN));
+    }
+}
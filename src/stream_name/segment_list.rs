@@ -1,6 +1,6 @@
 use uuid::Uuid;
 
-pub(crate) trait SegmentList {
+pub trait SegmentList {
     fn process(self) -> Option<Vec<String>>;
 }
 
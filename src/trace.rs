@@ -0,0 +1,436 @@
+// W3C trace context carried in `Metadata::trace_info`, so a request can be
+// followed from service to service through the messages it causes.
+//
+// While a consumer handles a message, the message's trace context is the
+// current context, and everything written in the meantime carries a child of
+// it in its `traceparent`. Writes outside of a handler can join a trace by
+// entering a context themselves, such as one parsed from an HTTP header.
+//
+// With a `tracing-opentelemetry` layer installed, the handling span's parent is
+// the message's context, and writes carry the OpenTelemetry context of the
+// current span instead, so messages join the traces the application exports.
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{SpanContext, TraceContextExt};
+use opentelemetry::Context;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use tracing::span::EnteredSpan;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::message_store::MessageData;
+use crate::Json;
+
+pub const TRACEPARENT: &str = "traceparent";
+const TRACE_INFO: &str = "trace_info";
+const VERSION: &str = "00";
+const SAMPLED: u8 = 0x01;
+
+thread_local! {
+    static CURRENT: RefCell<Option<TraceContext>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub span_id: String,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    // Starts a new trace
+    pub fn new() -> Self {
+        Self {
+            trace_id: format!("{:032x}", non_zero(rand::random::<u128>)),
+            span_id: span_id(),
+            sampled: true,
+        }
+    }
+
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+
+        if parts.len() < 4 {
+            return None;
+        }
+
+        let (version, trace_id, span_id, flags) = (parts[0], parts[1], parts[2], parts[3]);
+
+        if !is_hex(version, 2) || version == "ff" || (version == VERSION && parts.len() != 4) {
+            return None;
+        }
+
+        if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+            return None;
+        }
+
+        if is_zero(trace_id) || is_zero(span_id) {
+            return None;
+        }
+
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+
+        Some(Self {
+            trace_id: trace_id.to_lowercase(),
+            span_id: span_id.to_lowercase(),
+            sampled: flags & SAMPLED == SAMPLED,
+        })
+    }
+
+    pub fn from_trace_info(trace_info: &HashMap<String, String>) -> Option<Self> {
+        Self::parse(trace_info.get(TRACEPARENT)?)
+    }
+
+    // The context of an OpenTelemetry span, when it's a valid one
+    pub fn from_span_context(span_context: &SpanContext) -> Option<Self> {
+        if !span_context.is_valid() {
+            return None;
+        }
+
+        Some(Self {
+            trace_id: span_context.trace_id().to_string(),
+            span_id: span_context.span_id().to_string(),
+            sampled: span_context.is_sampled(),
+        })
+    }
+
+    // The same trace, from a new span whose parent is this one
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id.clone(),
+            span_id: span_id(),
+            sampled: self.sampled,
+        }
+    }
+
+    pub fn traceparent(&self) -> String {
+        let flags = if self.sampled { SAMPLED } else { 0 };
+
+        format!(
+            "{}-{}-{}-{:02x}",
+            VERSION, self.trace_id, self.span_id, flags
+        )
+    }
+
+    pub fn inject(&self, trace_info: &mut HashMap<String, String>) {
+        trace_info.insert(String::from(TRACEPARENT), self.traceparent());
+    }
+
+    pub fn current() -> Option<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    // Makes this the current context until the guard is dropped
+    pub fn enter(self) -> ContextGuard {
+        let previous = CURRENT.with(|current| current.replace(Some(self)));
+
+        ContextGuard { previous }
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct ContextGuard {
+    previous: Option<TraceContext>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+
+        CURRENT.with(|current| current.replace(previous));
+    }
+}
+
+// Sets the trace info of metadata about to be written to the current span's
+// OpenTelemetry context, or to a child of the current context without one,
+// returning None when there's neither
+pub(crate) fn inject(metadata: &Json) -> Option<Json> {
+    let propagated = match span_trace_info(&Span::current()) {
+        Some(propagated) => propagated,
+        None => {
+            let mut propagated = HashMap::new();
+            TraceContext::current()?.child().inject(&mut propagated);
+            propagated
+        }
+    };
+
+    let mut metadata = metadata.clone();
+
+    if !metadata.is_object() {
+        metadata = Json::Object(Default::default());
+    }
+
+    let trace_info = metadata
+        .as_object_mut()?
+        .entry(TRACE_INFO)
+        .or_insert_with(|| Json::Object(Default::default()));
+
+    if !trace_info.is_object() {
+        *trace_info = Json::Object(Default::default());
+    }
+
+    let trace_info = trace_info.as_object_mut()?;
+
+    for (key, value) in propagated {
+        trace_info.insert(key, Json::from(value));
+    }
+
+    Some(metadata)
+}
+
+// Propagated with the W3C format, the same as `TraceContext`
+fn span_trace_info(span: &Span) -> Option<HashMap<String, String>> {
+    let context = span.context();
+
+    if !context.span().span_context().is_valid() {
+        return None;
+    }
+
+    let mut trace_info = HashMap::new();
+    TraceContextPropagator::new().inject_context(&context, &mut trace_info);
+
+    Some(trace_info)
+}
+
+fn trace_info(message_data: &MessageData) -> HashMap<String, String> {
+    message_data
+        .metadata
+        .get(TRACE_INFO)
+        .and_then(Json::as_object)
+        .map(|trace_info| {
+            trace_info
+                .iter()
+                .filter_map(|(key, value)| Some((key.clone(), String::from(value.as_str()?))))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn extract(message_data: &MessageData) -> Option<TraceContext> {
+    TraceContext::from_trace_info(&trace_info(message_data))
+}
+
+// Enters a span for handling the message, continuing the message's trace or
+// starting a new one when it doesn't have one. The span's OpenTelemetry
+// context, when there is one, becomes the current context.
+pub(crate) fn handling(category: &str, message_data: &MessageData) -> (EnteredSpan, ContextGuard) {
+    let parent = extract(message_data);
+    let parent_context: Context = TraceContextPropagator::new().extract(&trace_info(message_data));
+
+    let span = tracing::info_span!(
+        "evt.handle",
+        category,
+        message_type = message_data.message_type.as_str(),
+        global_position = message_data.global_position,
+        trace_id = tracing::field::Empty,
+        span_id = tracing::field::Empty,
+        parent_span_id = parent.as_ref().map(|parent| parent.span_id.as_str()),
+    );
+
+    if parent_context.span().span_context().is_valid() {
+        let _ = span.set_parent(parent_context);
+    }
+
+    let context = TraceContext::from_span_context(span.context().span().span_context())
+        .unwrap_or_else(|| match &parent {
+            Some(parent) => parent.child(),
+            None => TraceContext::new(),
+        });
+
+    span.record("trace_id", context.trace_id.as_str());
+    span.record("span_id", context.span_id.as_str());
+
+    (span.entered(), context.enter())
+}
+
+fn span_id() -> String {
+    format!("{:016x}", non_zero(rand::random::<u64>))
+}
+
+fn non_zero<T: PartialEq + Default>(random: fn() -> T) -> T {
+    loop {
+        let value = random();
+
+        if value != T::default() {
+            return value;
+        }
+    }
+}
+
+fn is_hex(value: &str, length: usize) -> bool {
+    value.len() == length && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_zero(value: &str) -> bool {
+    value.chars().all(|c| c == '0')
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::message_store::{controls, Get, MessageData, Put};
+    use crate::messaging::Metadata;
+    use crate::stream_name;
+
+    use super::*;
+
+    const EXAMPLE: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    fn opentelemetry_subscriber() -> impl Subscriber + Send + Sync {
+        let tracer = SdkTracerProvider::builder().build().tracer("evt");
+
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer))
+    }
+
+    #[test]
+    fn parses_a_traceparent() {
+        let context = TraceContext::parse(EXAMPLE).unwrap();
+
+        assert_eq!("0af7651916cd43dd8448eb211c80319c", context.trace_id);
+        assert_eq!("b7ad6b7169203331", context.span_id);
+        assert!(context.sampled);
+        assert_eq!(EXAMPLE, context.traceparent());
+    }
+
+    #[test]
+    fn rejects_invalid_traceparents() {
+        assert_eq!(None, TraceContext::parse(""));
+        assert_eq!(
+            None,
+            TraceContext::parse("00-00000000000000000000000000000000-b7ad6b7169203331-01")
+        );
+        assert_eq!(
+            None,
+            TraceContext::parse("00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01")
+        );
+        assert_eq!(
+            None,
+            TraceContext::parse("ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+        );
+        assert_eq!(
+            None,
+            TraceContext::parse("00-0af7651916cd43dd8448eb211c80319c-b7ad6b716920333-01")
+        );
+    }
+
+    #[test]
+    fn children_continue_the_trace() {
+        let context = TraceContext::parse(EXAMPLE).unwrap();
+
+        let child = context.child();
+
+        assert_eq!(context.trace_id, child.trace_id);
+        assert_ne!(context.span_id, child.span_id);
+    }
+
+    #[test]
+    fn entering_sets_the_current_context_until_dropped() {
+        let context = TraceContext::new();
+
+        {
+            let _guard = context.clone().enter();
+
+            assert_eq!(Some(context), TraceContext::current());
+        }
+
+        assert_eq!(None, TraceContext::current());
+    }
+
+    #[test]
+    fn writes_the_current_trace_into_trace_info() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+        let context = TraceContext::parse(EXAMPLE).unwrap();
+
+        {
+            let _guard = context.clone().enter();
+            let _: MessageData = store
+                .put(&controls::new_example(), &stream_name, None)
+                .unwrap();
+        }
+
+        let written = store.get_last(&stream_name).unwrap().unwrap();
+        let metadata: Metadata = serde_json::from_value(written.metadata).unwrap();
+        let written = TraceContext::from_trace_info(&metadata.trace_info).unwrap();
+
+        assert_eq!(context.trace_id, written.trace_id);
+        assert_ne!(context.span_id, written.span_id);
+    }
+
+    #[test]
+    fn handling_continues_the_messages_trace() {
+        let mut message_data = controls::example();
+        message_data.metadata = serde_json::json!({
+            "trace_info": { "traceparent": EXAMPLE }
+        });
+
+        let (_span, _guard) = handling("someCategory", &message_data);
+        let current = TraceContext::current().unwrap();
+
+        assert_eq!("0af7651916cd43dd8448eb211c80319c", current.trace_id);
+        assert_ne!("b7ad6b7169203331", current.span_id);
+    }
+
+    #[test]
+    fn handling_starts_a_trace_for_messages_without_one() {
+        let (_span, _guard) = handling("someCategory", &controls::example());
+
+        assert!(TraceContext::current().is_some());
+    }
+
+    #[test]
+    fn handling_parents_the_opentelemetry_span_with_the_messages_context() {
+        let mut message_data = controls::example();
+        message_data.metadata = serde_json::json!({
+            "trace_info": { "traceparent": EXAMPLE }
+        });
+
+        tracing::subscriber::with_default(opentelemetry_subscriber(), || {
+            let (_span, _guard) = handling("someCategory", &message_data);
+            let context = Span::current().context();
+            let span_context = context.span().span_context().clone();
+            let current = TraceContext::current().unwrap();
+
+            assert_eq!(
+                "0af7651916cd43dd8448eb211c80319c",
+                span_context.trace_id().to_string()
+            );
+            assert_eq!(span_context.span_id().to_string(), current.span_id);
+        });
+    }
+
+    #[test]
+    fn writes_carry_the_current_opentelemetry_context() {
+        let mut store = controls::message_store();
+        let stream_name = stream_name::controls::unique_example();
+
+        let trace_id = tracing::subscriber::with_default(opentelemetry_subscriber(), || {
+            let span = tracing::info_span!("request");
+            let trace_id = span.context().span().span_context().trace_id();
+            let _entered = span.entered();
+
+            let _: MessageData = store
+                .put(&controls::new_example(), &stream_name, None)
+                .unwrap();
+
+            trace_id.to_string()
+        });
+
+        let written = store.get_last(&stream_name).unwrap().unwrap();
+        let metadata: Metadata = serde_json::from_value(written.metadata).unwrap();
+        let written = TraceContext::from_trace_info(&metadata.trace_info).unwrap();
+
+        assert_eq!(trace_id, written.trace_id);
+    }
+}